@@ -0,0 +1,446 @@
+//! Generates typed DEP contract bindings from the ABI JSON.
+//!
+//! The ABI defaults to the latest version under `abi/` and can be overridden
+//! with the `DEP_ABI_PATH` environment variable for any DEP-compatible
+//! deployment.
+
+use ethabi::{Contract, Event, Function, Param, ParamType, StateMutability};
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::path::Path;
+
+const DEFAULT_ABI_PATH: &str = "abi/dep-1.0.5.json";
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=DEP_ABI_PATH");
+    let abi_path = env::var("DEP_ABI_PATH").unwrap_or_else(|_| DEFAULT_ABI_PATH.to_string());
+    println!("cargo:rerun-if-changed={}", abi_path);
+    let file = File::open(&abi_path).unwrap_or_else(|e| panic!("open {}: {}", abi_path, e));
+    let contract = Contract::load(file).unwrap_or_else(|e| panic!("parse {}: {}", abi_path, e));
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("dep_bindings.rs");
+    fs::write(out, generate(&contract)).unwrap();
+}
+
+fn generate(contract: &Contract) -> String {
+    let mut functions: Vec<&Function> = contract.functions().collect();
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut events: Vec<&Event> = contract.events().collect();
+    events.sort_by(|a, b| a.name.cmp(&b.name));
+    let function_names = unique_names(functions.iter().map(|f| f.name.as_str()));
+    let event_names = unique_names(events.iter().map(|e| e.name.as_str()));
+
+    let mut code = String::new();
+    code.push_str("pub mod selectors {\n");
+    for (function, name) in functions.iter().zip(&function_names) {
+        writeln!(code, "    /// `{}`", function.signature()).unwrap();
+        writeln!(
+            code,
+            "    pub const {}: [u8; 4] = {:?};",
+            snake_case(name).to_uppercase(),
+            function.short_signature()
+        )
+        .unwrap();
+    }
+    code.push_str("}\n\n");
+
+    code.push_str("pub mod events {\n    use super::*;\n\n");
+    for (event, name) in events.iter().zip(&event_names) {
+        generate_event(&mut code, event, name);
+    }
+    let all_serializable = events
+        .iter()
+        .all(|event| event.inputs.iter().all(|input| serializable(&input.kind)));
+    generate_event_enum(&mut code, &event_names, all_serializable);
+    code.push_str("}\n\n");
+
+    for (function, name) in functions.iter().zip(&function_names) {
+        if function.outputs.len() > 1 && function.outputs.iter().all(|p| !p.name.is_empty()) {
+            generate_output_struct(&mut code, function, name);
+        }
+    }
+
+    code.push_str("impl Calls<'_> {\n");
+    for (function, name) in functions.iter().zip(&function_names) {
+        generate_call(&mut code, function, name);
+    }
+    code.push_str("}\n");
+    code
+}
+
+/// Disambiguates overloaded ABI entries by suffixing their position.
+fn unique_names<'a>(names: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let names: Vec<&str> = names.collect();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for name in &names {
+        *counts.entry(name).or_default() += 1;
+    }
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    names
+        .iter()
+        .map(|name| {
+            if counts[name] == 1 {
+                return name.to_string();
+            }
+            let index = seen.entry(name).or_default();
+            *index += 1;
+            format!("{}{}", name, index)
+        })
+        .collect()
+}
+
+fn generate_event(code: &mut String, event: &Event, name: &str) {
+    let struct_name = pascal_case(name);
+    writeln!(code, "    /// `{}`", event_signature(event)).unwrap();
+    let kinds = event.inputs.iter().map(|input| &input.kind);
+    writeln!(code, "    #[derive({})]", derives(kinds)).unwrap();
+    writeln!(code, "    pub struct {} {{", struct_name).unwrap();
+    for (i, input) in event.inputs.iter().enumerate() {
+        let ty = if input.indexed && is_dynamic(&input.kind) {
+            "H256".to_string()
+        } else {
+            rust_type(&input.kind)
+        };
+        writeln!(code, "        pub {}: {},", field_name(&input.name, i), ty).unwrap();
+    }
+    writeln!(code, "    }}\n").unwrap();
+
+    let data_types: Vec<String> = event
+        .inputs
+        .iter()
+        .filter(|input| !input.indexed)
+        .map(|input| param_type(&input.kind))
+        .collect();
+    writeln!(code, "    impl {} {{", struct_name).unwrap();
+    writeln!(
+        code,
+        "        pub const NAME: &'static str = {:?};",
+        event.name
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "        pub const SIGNATURE: H256 = H256({:?});",
+        event.signature().0
+    )
+    .unwrap();
+    writeln!(code).unwrap();
+    writeln!(
+        code,
+        "        pub fn decode(log: &Log) -> Result<Self, anyhow::Error> {{"
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "            if log.topics.first() != Some(&Self::SIGNATURE) {{\n                anyhow::bail!(\"not a {} log\");\n            }}",
+        event.name
+    )
+    .unwrap();
+    if !data_types.is_empty() {
+        writeln!(
+            code,
+            "            let mut data = ethabi::decode(&[{}], &log.data.0)?.into_iter();",
+            data_types.join(", ")
+        )
+        .unwrap();
+    }
+    if event.inputs.iter().any(|input| input.indexed) {
+        writeln!(
+            code,
+            "            let mut topics = log.topics.iter().skip(1);"
+        )
+        .unwrap();
+    }
+    writeln!(code, "            Ok({} {{", struct_name).unwrap();
+    for (i, input) in event.inputs.iter().enumerate() {
+        let token = if input.indexed {
+            format!("topic_token(topics.next(), {})?", param_type(&input.kind))
+        } else {
+            "next_token(&mut data)?".to_string()
+        };
+        writeln!(
+            code,
+            "                {}: Tokenizable::from_token({})?,",
+            field_name(&input.name, i),
+            token
+        )
+        .unwrap();
+    }
+    writeln!(code, "            }})\n        }}\n    }}\n").unwrap();
+}
+
+fn generate_event_enum(code: &mut String, names: &[String], serializable: bool) {
+    writeln!(code, "    /// Any event declared in the DEP ABI.").unwrap();
+    if serializable {
+        writeln!(
+            code,
+            "    #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]"
+        )
+        .unwrap();
+    } else {
+        writeln!(code, "    #[derive(Debug, Clone, PartialEq)]").unwrap();
+    }
+    writeln!(code, "    pub enum DepEvent {{").unwrap();
+    for name in names {
+        writeln!(code, "        {0}({0}),", pascal_case(name)).unwrap();
+    }
+    writeln!(code, "    }}\n").unwrap();
+    writeln!(code, "    impl DepEvent {{").unwrap();
+    writeln!(
+        code,
+        "        /// Decodes a contract log, or returns `None` for logs of other events."
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "        pub fn decode(log: &Log) -> Result<Option<Self>, anyhow::Error> {{"
+    )
+    .unwrap();
+    writeln!(code, "            let topic = match log.topics.first() {{\n                Some(topic) => *topic,\n                None => return Ok(None),\n            }};").unwrap();
+    for name in names {
+        writeln!(
+            code,
+            "            if topic == {0}::SIGNATURE {{\n                return Ok(Some(DepEvent::{0}({0}::decode(log)?)));\n            }}",
+            pascal_case(name)
+        )
+        .unwrap();
+    }
+    writeln!(code, "            Ok(None)\n        }}\n").unwrap();
+    writeln!(code, "        pub fn name(&self) -> &'static str {{").unwrap();
+    writeln!(code, "            match self {{").unwrap();
+    for name in names {
+        writeln!(
+            code,
+            "                DepEvent::{0}(_) => {0}::NAME,",
+            pascal_case(name)
+        )
+        .unwrap();
+    }
+    writeln!(code, "            }}\n        }}\n    }}").unwrap();
+}
+
+fn generate_output_struct(code: &mut String, function: &Function, name: &str) {
+    let struct_name = format!("{}Output", pascal_case(name));
+    writeln!(code, "/// Return values of `{}`.", function.signature()).unwrap();
+    let kinds = function.outputs.iter().map(|output| &output.kind);
+    writeln!(code, "#[derive({})]", derives(kinds)).unwrap();
+    writeln!(code, "pub struct {} {{", struct_name).unwrap();
+    for (i, output) in function.outputs.iter().enumerate() {
+        writeln!(
+            code,
+            "    pub {}: {},",
+            field_name(&output.name, i),
+            rust_type(&output.kind)
+        )
+        .unwrap();
+    }
+    writeln!(code, "}}\n").unwrap();
+    writeln!(code, "impl Detokenize for {} {{", struct_name).unwrap();
+    writeln!(
+        code,
+        "    fn from_tokens(tokens: Vec<Token>) -> Result<Self, web3::contract::Error> {{"
+    )
+    .unwrap();
+    writeln!(code, "        let mut tokens = tokens.into_iter();").unwrap();
+    writeln!(code, "        Ok({} {{", struct_name).unwrap();
+    for (i, output) in function.outputs.iter().enumerate() {
+        writeln!(
+            code,
+            "            {}: Tokenizable::from_token(next_output(&mut tokens)?)?,",
+            field_name(&output.name, i)
+        )
+        .unwrap();
+    }
+    writeln!(code, "        }})\n    }}\n}}\n").unwrap();
+}
+
+fn generate_call(code: &mut String, function: &Function, name: &str) {
+    let args: Vec<String> = function
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| format!("{}: {}", field_name(&input.name, i), arg_type(&input.kind)))
+        .collect();
+    let params: Vec<String> = function
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| match input.kind {
+            ParamType::String => format!("{}.to_string()", field_name(&input.name, i)),
+            _ => field_name(&input.name, i),
+        })
+        .collect();
+    let params = match params.len() {
+        0 => "()".to_string(),
+        1 => format!("({},)", params[0]),
+        _ => format!("({})", params.join(", ")),
+    };
+    let read_only = matches!(
+        function.state_mutability,
+        StateMutability::View | StateMutability::Pure
+    );
+    let ret = if !read_only {
+        "TransactionReceipt".to_string()
+    } else {
+        output_type(function, name)
+    };
+    let call = if read_only { "query" } else { "write_contract" };
+
+    writeln!(code, "    /// `{}`", function.signature()).unwrap();
+    writeln!(
+        code,
+        "    pub async fn {}(&self{}) -> Result<{}, anyhow::Error> {{",
+        field_name(&snake_case(name), 0),
+        args.iter()
+            .map(|arg| format!(", {}", arg))
+            .collect::<String>(),
+        ret
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "        self.client.{}({:?}, {}).await\n    }}\n",
+        call, function.name, params
+    )
+    .unwrap();
+}
+
+fn output_type(function: &Function, name: &str) -> String {
+    match function.outputs.as_slice() {
+        [] => "()".to_string(),
+        [output] => rust_type(&output.kind),
+        outputs if outputs.iter().all(|p| !p.name.is_empty()) => {
+            format!("{}Output", pascal_case(name))
+        }
+        outputs => format!(
+            "({})",
+            outputs
+                .iter()
+                .map(|p: &Param| rust_type(&p.kind))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn event_signature(event: &Event) -> String {
+    let kinds: Vec<String> = event
+        .inputs
+        .iter()
+        .map(|input| input.kind.to_string())
+        .collect();
+    format!("{}({})", event.name, kinds.join(","))
+}
+
+fn is_dynamic(kind: &ParamType) -> bool {
+    matches!(
+        kind,
+        ParamType::String | ParamType::Bytes | ParamType::Array(_) | ParamType::Tuple(_)
+    ) || matches!(kind, ParamType::FixedArray(inner, _) if is_dynamic(inner))
+}
+
+fn rust_type(kind: &ParamType) -> String {
+    match kind {
+        ParamType::Address => "Address".to_string(),
+        ParamType::Bool => "bool".to_string(),
+        ParamType::String => "String".to_string(),
+        ParamType::Bytes => "Vec<u8>".to_string(),
+        ParamType::Uint(bits) if *bits <= 64 => "u64".to_string(),
+        ParamType::Uint(_) => "U256".to_string(),
+        ParamType::FixedBytes(32) => "H256".to_string(),
+        ParamType::FixedBytes(len) => format!("[u8; {}]", len),
+        ParamType::Array(inner) => format!("Vec<{}>", rust_type(inner)),
+        _ => "Token".to_string(),
+    }
+}
+
+/// Whether `rust_type(kind)` implements `serde::Serialize`.
+fn serializable(kind: &ParamType) -> bool {
+    match kind {
+        ParamType::Array(inner) => serializable(inner),
+        _ => rust_type(kind) != "Token",
+    }
+}
+
+fn derives<'a>(mut kinds: impl Iterator<Item = &'a ParamType>) -> &'static str {
+    if kinds.all(serializable) {
+        "Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize"
+    } else {
+        "Debug, Clone, PartialEq"
+    }
+}
+
+fn arg_type(kind: &ParamType) -> String {
+    match kind {
+        ParamType::String => "&str".to_string(),
+        _ => rust_type(kind),
+    }
+}
+
+fn param_type(kind: &ParamType) -> String {
+    match kind {
+        ParamType::Address => "ParamType::Address".to_string(),
+        ParamType::Bool => "ParamType::Bool".to_string(),
+        ParamType::String => "ParamType::String".to_string(),
+        ParamType::Bytes => "ParamType::Bytes".to_string(),
+        ParamType::Uint(bits) => format!("ParamType::Uint({})", bits),
+        ParamType::Int(bits) => format!("ParamType::Int({})", bits),
+        ParamType::FixedBytes(len) => format!("ParamType::FixedBytes({})", len),
+        ParamType::Array(inner) => format!("ParamType::Array(Box::new({}))", param_type(inner)),
+        ParamType::FixedArray(inner, len) => format!(
+            "ParamType::FixedArray(Box::new({}), {})",
+            param_type(inner),
+            len
+        ),
+        ParamType::Tuple(inner) => format!(
+            "ParamType::Tuple(vec![{}])",
+            inner.iter().map(param_type).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+/// A Rust identifier for an ABI parameter, `arg{i}` when it is unnamed.
+fn field_name(name: &str, i: usize) -> String {
+    let name = snake_case(name.trim_start_matches('_'));
+    if name.is_empty() {
+        return format!("arg{}", i);
+    }
+    match name.as_str() {
+        "as" | "async" | "await" | "box" | "const" | "crate" | "enum" | "fn" | "impl" | "in"
+        | "let" | "loop" | "match" | "mod" | "move" | "ref" | "self" | "static" | "struct"
+        | "super" | "trait" | "type" | "use" | "where" | "while" => format!("r#{}", name),
+        _ => name,
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut out = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev.is_lowercase() || prev.is_ascii_digit() || (prev.is_uppercase() && next_lower) {
+                out.push('_');
+            }
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
+
+fn pascal_case(name: &str) -> String {
+    snake_case(name)
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,70 @@
+//! DEP ABI versions compiled into the crate.
+//!
+//! Each version matches the string returned by the contract's
+//! `implementationVersion()`. Add new versions under `abi/` as the contract
+//! is upgraded.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AbiVersion {
+    V1_0_5,
+}
+
+impl AbiVersion {
+    /// The version the generated bindings are built from by default.
+    pub const LATEST: AbiVersion = AbiVersion::V1_0_5;
+
+    pub fn all() -> &'static [AbiVersion] {
+        &[AbiVersion::V1_0_5]
+    }
+
+    /// The `implementationVersion()` string of the contract.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AbiVersion::V1_0_5 => "1.0.5",
+        }
+    }
+
+    /// The ABI JSON for this version.
+    pub fn json(&self) -> &'static [u8] {
+        match self {
+            AbiVersion::V1_0_5 => include_bytes!("../abi/dep-1.0.5.json"),
+        }
+    }
+}
+
+impl fmt::Display for AbiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AbiVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let version = s.trim().trim_start_matches('v');
+        AbiVersion::all()
+            .iter()
+            .find(|known| known.as_str() == version)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("no embedded DEP ABI for version {}", s))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_embedded_abis_parse() {
+        for version in AbiVersion::all() {
+            let abi = web3::ethabi::Contract::load(version.json()).unwrap();
+            assert!(abi.function("implementationVersion").is_ok());
+            assert_eq!(version.as_str().parse::<AbiVersion>().unwrap(), *version);
+        }
+        assert!("0.9.0".parse::<AbiVersion>().is_err());
+    }
+}
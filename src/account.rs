@@ -0,0 +1,244 @@
+//! Mapping between Deeper substrate accounts and EVM addresses.
+//!
+//! Deeper nodes are identified by SS58 accounts while DEP stores H160s. The
+//! functions here assume the defaults of Frontier's `pallet-evm`:
+//!
+//! - a substrate account calling into the EVM does so as the first 20 bytes
+//!   of its public key (`EnsureAddressTruncated`), see `evm_address`;
+//! - an EVM address owns the substrate account `blake2_256("evm:" ++ h160)`
+//!   (`HashedAddressMapping<BlakeTwo256>`), see `substrate_account`;
+//! - accounts are shown with substrate's generic SS58 prefix 42.
+//!
+//! The repository has no record of how the Deeper runtime configures
+//! `pallet-evm` or its SS58 prefix, and these defaults have not been checked
+//! against accounts on the live chain. `AccountMapping` holds the hashed
+//! mapping's prefix and the SS58 prefix, so a network profile can override
+//! them once they are confirmed.
+//!
+//! Neither mapping can be inverted from the result alone. `AccountIndex`
+//! inverts `evm_address` for a known set of accounts, such as a node
+//! registry.
+
+use serde::{Deserialize, Serialize};
+use sp_core::crypto::{AccountId32, Ss58AddressFormat, Ss58Codec};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use web3::types::Address;
+
+/// The generic substrate SS58 prefix, assumed for the Deeper chain.
+pub const DEEPER_SS58_PREFIX: u16 = 42;
+
+/// The bytes Frontier's `HashedAddressMapping` hashes in front of an H160.
+pub const HASHED_ADDRESS_PREFIX: &str = "evm:";
+
+/// How a chain shows accounts and maps EVM addresses to the accounts holding
+/// their balance. The default is Frontier's, with SS58 prefix 42.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountMapping {
+    pub ss58_prefix: u16,
+    /// Hashed with blake2-256 in front of an H160 to derive the substrate
+    /// account holding its balance.
+    pub hashed_address_prefix: String,
+}
+
+impl Default for AccountMapping {
+    fn default() -> Self {
+        AccountMapping {
+            ss58_prefix: DEEPER_SS58_PREFIX,
+            hashed_address_prefix: HASHED_ADDRESS_PREFIX.to_string(),
+        }
+    }
+}
+
+impl AccountMapping {
+    /// The substrate account holding an EVM address's balance.
+    pub fn substrate_account(&self, address: Address) -> AccountId32 {
+        let mut data = self.hashed_address_prefix.as_bytes().to_vec();
+        data.extend_from_slice(address.as_bytes());
+        AccountId32::new(sp_core::blake2_256(&data))
+    }
+
+    /// Formats an account with the chain's prefix.
+    pub fn to_ss58(&self, account: &AccountId32) -> String {
+        account.to_ss58check_with_version(Ss58AddressFormat::custom(self.ss58_prefix))
+    }
+}
+
+/// The H160 a substrate account acts as in the EVM.
+pub fn evm_address(account: &AccountId32) -> Address {
+    let bytes: &[u8; 32] = account.as_ref();
+    Address::from_slice(&bytes[..20])
+}
+
+/// The substrate account holding an EVM address's balance under the
+/// default mapping.
+pub fn substrate_account(address: Address) -> AccountId32 {
+    AccountMapping::default().substrate_account(address)
+}
+
+/// Parses an SS58 account with any network prefix.
+pub fn parse_ss58(account: &str) -> Result<AccountId32, anyhow::Error> {
+    AccountId32::from_ss58check(account.trim())
+        .map_err(|e| anyhow::anyhow!("invalid SS58 account {}: {:?}", account, e))
+}
+
+/// Formats an account with the default prefix.
+pub fn to_ss58(account: &AccountId32) -> String {
+    AccountMapping::default().to_ss58(account)
+}
+
+/// A node given either as an H160 or as the SS58 account it maps to with
+/// `evm_address`. Accepted wherever receivers are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeAddress(pub Address);
+
+impl FromStr for NodeAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let hex = s.strip_prefix("0x").unwrap_or(s);
+        if hex.len() == 40 {
+            return Ok(NodeAddress(hex.parse()?));
+        }
+        Ok(NodeAddress(evm_address(&parse_ss58(s)?)))
+    }
+}
+
+impl fmt::Display for NodeAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl From<NodeAddress> for Address {
+    fn from(node: NodeAddress) -> Self {
+        node.0
+    }
+}
+
+impl From<Address> for NodeAddress {
+    fn from(address: Address) -> Self {
+        NodeAddress(address)
+    }
+}
+
+impl From<&AccountId32> for NodeAddress {
+    fn from(account: &AccountId32) -> Self {
+        NodeAddress(evm_address(account))
+    }
+}
+
+/// Reverse lookup from EVM addresses to known substrate accounts.
+#[derive(Debug, Clone, Default)]
+pub struct AccountIndex {
+    accounts: HashMap<Address, AccountId32>,
+}
+
+impl AccountIndex {
+    pub fn new() -> Self {
+        AccountIndex::default()
+    }
+
+    /// Indexes SS58 accounts, failing on the first invalid one.
+    pub fn from_ss58<'a>(
+        accounts: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut index = AccountIndex::new();
+        for account in accounts {
+            index.insert(parse_ss58(account)?);
+        }
+        Ok(index)
+    }
+
+    /// Returns the account previously indexed under the same EVM address.
+    pub fn insert(&mut self, account: AccountId32) -> Option<AccountId32> {
+        self.accounts.insert(evm_address(&account), account)
+    }
+
+    /// The known account acting as `address` in the EVM, e.g. the node of a
+    /// `RaceTask` event.
+    pub fn lookup(&self, address: Address) -> Option<&AccountId32> {
+        self.accounts.get(&address)
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sp_keyring::AccountKeyring;
+
+    #[test]
+    fn test_mappings() {
+        let alice = AccountKeyring::Alice.to_account_id();
+        assert_eq!(
+            to_ss58(&alice),
+            "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"
+        );
+        let address = evm_address(&alice);
+        assert_eq!(
+            address,
+            "d43593c715fdd31c61141abd04a99fd6822c8558".parse().unwrap()
+        );
+        assert_eq!(
+            to_ss58(&substrate_account(address)),
+            "5FrLxJsyJ5x9n2rmxFwosFraxFCKcXZDngRLNectCn64UjtZ"
+        );
+    }
+
+    #[test]
+    fn test_custom_mapping() {
+        let alice = AccountKeyring::Alice.to_account_id();
+        let address = evm_address(&alice);
+        let mapping = AccountMapping {
+            ss58_prefix: 0,
+            hashed_address_prefix: "other:".to_string(),
+        };
+        // prefix 0 is Polkadot's
+        assert_eq!(
+            mapping.to_ss58(&alice),
+            "15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5"
+        );
+        assert_ne!(
+            mapping.substrate_account(address),
+            substrate_account(address)
+        );
+        assert_eq!(
+            AccountMapping::default().substrate_account(address),
+            substrate_account(address)
+        );
+    }
+
+    #[test]
+    fn test_node_addresses() {
+        let alice = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY";
+        let expected = NodeAddress("d43593c715fdd31c61141abd04a99fd6822c8558".parse().unwrap());
+        assert_eq!(alice.parse::<NodeAddress>().unwrap(), expected);
+        assert_eq!(
+            "0xd43593c715fdd31c61141abd04a99fd6822c8558"
+                .parse::<NodeAddress>()
+                .unwrap(),
+            expected
+        );
+        assert!("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQZ"
+            .parse::<NodeAddress>()
+            .is_err());
+
+        let index = AccountIndex::from_ss58([alice]).unwrap();
+        assert_eq!(
+            index.lookup(expected.0).map(to_ss58).as_deref(),
+            Some(alice)
+        );
+        assert_eq!(index.lookup(Address::zero()), None);
+    }
+}
@@ -0,0 +1,153 @@
+//! Typed DEP contract bindings generated from the ABI by `build.rs`.
+//!
+//! `Client::calls()` exposes every ABI function with its exact argument and
+//! return types; the methods on `Client` itself are conveniences layered on
+//! top. Event structs and the `DepEvent` enum live in `events`, and the
+//! 4-byte function selectors in `selectors`. `Calls::call` reaches any
+//! read-only function by its ABI name, with arguments given as text.
+
+use crate::account::NodeAddress;
+use crate::Client;
+use anyhow::Context;
+use web3::contract::tokens::{Detokenize, Tokenizable};
+use web3::ethabi::token::{LenientTokenizer, Tokenizer};
+use web3::ethabi::{self, Function, ParamType, StateMutability, Token};
+use web3::types::{Address, Log, TransactionReceipt, H256, U256};
+
+/// Typed access to every function in the DEP ABI.
+#[derive(Debug, Clone, Copy)]
+pub struct Calls<'a> {
+    client: &'a Client,
+}
+
+impl Client {
+    pub fn calls(&self) -> Calls<'_> {
+        Calls { client: self }
+    }
+}
+
+fn next_token(data: &mut impl Iterator<Item = Token>) -> Result<Token, anyhow::Error> {
+    data.next()
+        .ok_or_else(|| anyhow::anyhow!("log data has fewer values than the event declares"))
+}
+
+fn next_output(tokens: &mut impl Iterator<Item = Token>) -> Result<Token, web3::contract::Error> {
+    tokens
+        .next()
+        .ok_or_else(|| web3::contract::Error::InvalidOutputType("missing return value".to_string()))
+}
+
+/// Decodes an indexed event parameter. Dynamic types are only stored as
+/// their keccak hash, which is returned as-is.
+fn topic_token(topic: Option<&H256>, kind: ParamType) -> Result<Token, anyhow::Error> {
+    let topic =
+        topic.ok_or_else(|| anyhow::anyhow!("log has fewer topics than the event declares"))?;
+    match kind {
+        ParamType::String | ParamType::Bytes | ParamType::Array(_) | ParamType::Tuple(_) => {
+            Ok(Token::FixedBytes(topic.as_bytes().to_vec()))
+        }
+        kind => Ok(ethabi::decode(&[kind], topic.as_bytes())?.remove(0)),
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/dep_bindings.rs"));
+
+impl Calls<'_> {
+    /// Calls a read-only function by its ABI name, e.g. `userTask`, and
+    /// returns its outputs with their names (or positions, when unnamed).
+    /// Addresses may be H160 or SS58 and integers decimal.
+    pub async fn call(
+        &self,
+        name: &str,
+        args: &[String],
+    ) -> Result<Vec<(String, Token)>, anyhow::Error> {
+        let abi = self.client.contract.abi();
+        let function = match abi.function(name) {
+            Ok(function) if is_read_only(function) => function,
+            Ok(_) => anyhow::bail!("{} sends a transaction, use its own subcommand", name),
+            Err(_) => anyhow::bail!(
+                "unknown read-only function {:?}, expected one of {:?}",
+                name,
+                abi.functions()
+                    .filter(|function| is_read_only(function))
+                    .map(|function| function.name.as_str())
+                    .collect::<Vec<_>>()
+            ),
+        };
+        let params = call_params(function, args)?;
+        let outputs = self.client.query_tokens(&function.name, &params).await?;
+        Ok(function
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(i, output)| match output.name.as_str() {
+                "" => i.to_string(),
+                name => name.to_string(),
+            })
+            .zip(outputs)
+            .collect())
+    }
+}
+
+fn is_read_only(function: &Function) -> bool {
+    matches!(
+        function.state_mutability,
+        StateMutability::View | StateMutability::Pure
+    )
+}
+
+/// Parses text arguments into the function's parameter types.
+fn call_params(function: &Function, args: &[String]) -> Result<Vec<Token>, anyhow::Error> {
+    if args.len() != function.inputs.len() {
+        anyhow::bail!(
+            "{} takes {} arguments, got {}",
+            function.signature(),
+            function.inputs.len(),
+            args.len()
+        );
+    }
+    function
+        .inputs
+        .iter()
+        .zip(args)
+        .map(|(input, arg)| match input.kind {
+            ParamType::Address => Ok(Token::Address(arg.parse::<NodeAddress>()?.into())),
+            ref kind => LenientTokenizer::tokenize(kind, arg)
+                .with_context(|| format!("invalid {} argument {:?}", kind, arg)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::abi::AbiVersion;
+
+    #[test]
+    fn test_call_params() {
+        let client = Client::with_abi_version(
+            "http://127.0.0.1:1",
+            "9397AA12576cEc2A37C60f76d2FB31b31b5E5c7F",
+            AbiVersion::LATEST,
+            ethers::signers::LocalWallet::new(&mut rand::thread_rng()),
+        )
+        .unwrap();
+        let function = client.contract.abi().function("taskInfo").unwrap();
+        assert!(is_read_only(function));
+        assert_eq!(
+            call_params(function, &["7".to_string()]).unwrap(),
+            vec![Token::Uint(7.into())]
+        );
+        assert!(call_params(function, &["seven".to_string()]).is_err());
+        assert!(call_params(function, &[]).is_err());
+
+        let function = client.contract.abi().function("addressWhitelist").unwrap();
+        let address = "0x27fddef298618b512fa6d281db0e32e0f38d15d3";
+        assert_eq!(
+            call_params(function, &[address.to_string()]).unwrap(),
+            vec![Token::Address(address.parse().unwrap())]
+        );
+        let function = client.contract.abi().function("stopTask").unwrap();
+        assert!(!is_read_only(function));
+    }
+}
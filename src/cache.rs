@@ -0,0 +1,262 @@
+//! Read-through cache for contract values that rarely change.
+//!
+//! Each cached method has its own time to live. When a cache invalidator is
+//! running, entries are also dropped as soon as the chain shows the value may
+//! have changed: a setter call, a role event or a task duration extension.
+
+use crate::events::{EventBatch, EventWatcher};
+use crate::Client;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use web3::ethabi::{self, Token};
+
+/// Per-method time to live. Methods without an entry are never cached.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    ttls: HashMap<String, Duration>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        let minutes = |n: u64| Duration::from_secs(n * 60);
+        CacheConfig::empty()
+            .with_ttl("implementationVersion", minutes(60))
+            .with_ttl("startDay", Duration::MAX)
+            .with_ttl("owner", minutes(10))
+            .with_ttl("proofUnit", minutes(5))
+            .with_ttl("blockUintPrice", minutes(5))
+            .with_ttl("raceTimeout", minutes(5))
+            .with_ttl("completeTimeout", minutes(5))
+            .with_ttl("creditThreshold", minutes(5))
+    }
+}
+
+impl CacheConfig {
+    /// A configuration that caches nothing but finished tasks, and those
+    /// only while an invalidator runs.
+    pub fn empty() -> Self {
+        CacheConfig {
+            ttls: HashMap::new(),
+        }
+    }
+
+    /// Caches `method` (the ABI function name) for `ttl`. `Duration::MAX`
+    /// keeps the value until it is invalidated.
+    pub fn with_ttl(mut self, method: &str, ttl: Duration) -> Self {
+        self.ttls.insert(method.to_string(), ttl);
+        self
+    }
+
+    /// Stops caching `method`.
+    pub fn without(mut self, method: &str) -> Self {
+        self.ttls.remove(method);
+        self
+    }
+
+    pub fn ttl(&self, method: &str) -> Option<Duration> {
+        self.ttls.get(method).copied()
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    tokens: Vec<Token>,
+    expires: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Cache {
+    config: CacheConfig,
+    entries: Mutex<HashMap<(String, Vec<u8>), Entry>>,
+    invalidated: AtomicBool,
+}
+
+impl Cache {
+    pub(crate) fn new(config: CacheConfig) -> Self {
+        Cache {
+            config,
+            entries: Mutex::new(HashMap::new()),
+            invalidated: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether a cache invalidator follows the chain for this cache, so
+    /// entries may be kept until an invalidation.
+    pub(crate) fn is_invalidated(&self) -> bool {
+        self.invalidated.load(Ordering::Relaxed)
+    }
+
+    fn key(method: &str, params: &[Token]) -> (String, Vec<u8>) {
+        (method.to_string(), ethabi::encode(params))
+    }
+
+    pub(crate) fn get(&self, method: &str, params: &[Token]) -> Option<Vec<Token>> {
+        let mut entries = self.entries.lock().unwrap();
+        let key = Cache::key(method, params);
+        match entries.get(&key) {
+            Some(entry) if entry.expires.is_none_or(|at| at > Instant::now()) => {
+                Some(entry.tokens.clone())
+            }
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Stores a value for the method's configured TTL, if it has one.
+    pub(crate) fn insert(&self, method: &str, params: &[Token], tokens: Vec<Token>) {
+        if let Some(ttl) = self.config.ttl(method) {
+            self.store(method, params, tokens, Instant::now().checked_add(ttl));
+        }
+    }
+
+    /// Stores a value that only an invalidation can remove.
+    pub(crate) fn insert_permanent(&self, method: &str, params: &[Token], tokens: Vec<Token>) {
+        self.store(method, params, tokens, None);
+    }
+
+    fn store(&self, method: &str, params: &[Token], tokens: Vec<Token>, expires: Option<Instant>) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(Cache::key(method, params), Entry { tokens, expires });
+    }
+
+    pub(crate) fn invalidate(&self, method: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|(cached, _), _| cached != method);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Drops every entry the activity in `batch` may have made stale.
+    pub(crate) fn apply(&self, client: &Client, batch: &EventBatch) {
+        for call in &batch.calls {
+            for method in invalidated_by_call(&call.function) {
+                self.invalidate(method);
+            }
+        }
+        for log in &batch.logs {
+            for method in client.event_name(log).map_or(&[][..], invalidated_by_event) {
+                self.invalidate(method);
+            }
+        }
+    }
+}
+
+/// The cached reads a successful call to `function` makes stale.
+fn invalidated_by_call(function: &str) -> &'static [&'static str] {
+    match function {
+        "setProofUnit" => &["proofUnit"],
+        "setRaceTimeout" => &["raceTimeout"],
+        "setCompleteTimeout" => &["completeTimeout", "taskInfo"],
+        "setBlockUnitPrice" => &["blockUintPrice"],
+        "setCreditThreshold" => &["creditThreshold"],
+        "setAddressWhitelist" => &["addressWhitelist"],
+        "updateRewardPoint" => &["userRewardPoint", "getUserRewardPointer"],
+        _ => &[],
+    }
+}
+
+/// The cached reads an event makes stale.
+fn invalidated_by_event(event: &str) -> &'static [&'static str] {
+    match event {
+        "RoleGranted" | "RoleRevoked" | "RoleAdminChanged" => &[
+            "hasRole",
+            "getRoleAdmin",
+            "getRoleMember",
+            "getRoleMemberCount",
+        ],
+        "AddTaskDuration" => &["taskInfo"],
+        "AddImagePersistenceWhitelist" => &["userSetWhiteImage", "imageWhiteListStatus"],
+        _ => &[],
+    }
+}
+
+impl Client {
+    /// Returns a client that serves configured reads from a cache shared by
+    /// all of its clones. Reads pinned with `at_block` bypass the cache.
+    pub fn with_cache(&self, config: CacheConfig) -> Client {
+        Client {
+            cache: Some(Arc::new(Cache::new(config))),
+            ..self.clone()
+        }
+    }
+
+    /// Drops every cached value.
+    pub fn clear_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+
+    /// Follows the chain from the current head and invalidates cached values
+    /// whenever a matching setter call or event shows up.
+    pub async fn spawn_cache_invalidator(
+        &self,
+        poll_interval: Duration,
+    ) -> Result<JoinHandle<()>, anyhow::Error> {
+        let cache = match &self.cache {
+            Some(cache) => cache.clone(),
+            None => anyhow::bail!("cache is not enabled on this client"),
+        };
+        let head = self.eth.block_number().await?.as_u64();
+        let client = self.clone();
+        let mut watcher = EventWatcher::new(self, head + 1).with_calls(true);
+        cache.invalidated.store(true, Ordering::Relaxed);
+        Ok(tokio::spawn(async move {
+            loop {
+                match watcher.poll().await {
+                    Ok(batch) => cache.apply(&client, &batch),
+                    Err(e) => log::warn!("cache invalidator poll failed: {}", e),
+                }
+                tokio::time::sleep(poll_interval).await;
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use web3::types::U256;
+
+    #[test]
+    fn test_ttl_and_invalidation() {
+        let cache = Cache::new(
+            CacheConfig::empty()
+                .with_ttl("raceTimeout", Duration::MAX)
+                .with_ttl("owner", Duration::ZERO),
+        );
+        let value = vec![Token::Uint(U256::from(1200))];
+        cache.insert("raceTimeout", &[], value.clone());
+        cache.insert("owner", &[], value.clone());
+        cache.insert("taskSum", &[], value.clone());
+        assert_eq!(cache.get("raceTimeout", &[]), Some(value.clone()));
+        assert_eq!(cache.get("owner", &[]), None);
+        assert_eq!(cache.get("taskSum", &[]), None);
+
+        for method in invalidated_by_call("setRaceTimeout") {
+            cache.invalidate(method);
+        }
+        assert_eq!(cache.get("raceTimeout", &[]), None);
+    }
+
+    #[test]
+    fn test_entries_are_keyed_by_params() {
+        let cache = Cache::new(CacheConfig::empty());
+        let task = |id: u64| vec![Token::Uint(U256::from(id))];
+        cache.insert_permanent("taskInfo", &task(1), task(10));
+        assert_eq!(cache.get("taskInfo", &task(1)), Some(task(10)));
+        assert_eq!(cache.get("taskInfo", &task(2)), None);
+    }
+}
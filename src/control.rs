@@ -0,0 +1,337 @@
+//! Authentication of `StopTask` and `ResetRunners` control events.
+//!
+//! `stopTask` and `resetRunners` only emit events: the contract checks
+//! neither the caller nor the task, so anyone can emit them. The control
+//! plane looks up the sender of the emitting transaction and only authorizes
+//! a `StopTask` sent by the task's publisher or the contract owner. A
+//! `ResetRunners` from the owner resets every addressed runner; one from a
+//! publisher only covers that publisher's tasks. Everything else is flagged
+//! as rejected instead of obeyed.
+
+use crate::events::{DepEvent, DepLog, EventWatcher};
+use crate::Client;
+use std::collections::{HashMap, VecDeque};
+use web3::types::{Address, BlockId, BlockNumber, TransactionId, H256};
+
+/// Why a sender may issue a control command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authority {
+    Owner,
+    Publisher,
+    /// Anyone may emit a `ResetRunners`; unless it is the owner, the sender
+    /// is trusted with nothing but the tasks it published, which the
+    /// action's `ResetScope` names. This is not an authorization to act on
+    /// the event as a whole.
+    Scoped,
+}
+
+/// The tasks a `ResetRunners` applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetScope {
+    All,
+    PublishedBy(Address),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlAction {
+    StopTask {
+        task_id: u64,
+    },
+    ResetRunners {
+        /// Empty for every runner.
+        receivers: Vec<Address>,
+        scope: ResetScope,
+    },
+}
+
+impl ControlAction {
+    /// Whether a runner with `address` is addressed.
+    pub fn addresses(&self, address: Address) -> bool {
+        match self {
+            ControlAction::StopTask { .. } => true,
+            ControlAction::ResetRunners { receivers, .. } => {
+                receivers.is_empty() || receivers.contains(&address)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlMessage {
+    Authorized {
+        action: ControlAction,
+        sender: Address,
+        authority: Authority,
+        block_number: u64,
+        transaction_hash: H256,
+    },
+    Rejected {
+        event: DepEvent,
+        sender: Address,
+        reason: String,
+        block_number: u64,
+        transaction_hash: H256,
+    },
+}
+
+/// Decides whether `sender` may issue a control event, given the contract
+/// owner and, for `StopTask`, the task's publisher (`None` for unknown
+/// tasks). Returns `None` for other events.
+pub fn authorize(
+    event: &DepEvent,
+    sender: Address,
+    owner: Address,
+    publisher: Option<Address>,
+) -> Option<Result<(ControlAction, Authority), String>> {
+    match event {
+        DepEvent::StopTask(stop) => {
+            if stop.task_id > u64::MAX.into() || publisher.is_none() {
+                return Some(Err(format!("task {} does not exist", stop.task_id)));
+            }
+            let action = ControlAction::StopTask {
+                task_id: stop.task_id.low_u64(),
+            };
+            Some(if sender == owner {
+                Ok((action, Authority::Owner))
+            } else if publisher == Some(sender) {
+                Ok((action, Authority::Publisher))
+            } else {
+                Err(format!(
+                    "{:?} is neither the publisher of task {} nor the owner",
+                    sender, stop.task_id
+                ))
+            })
+        }
+        DepEvent::ResetRunners(reset) => {
+            let (scope, authority) = if sender == owner {
+                (ResetScope::All, Authority::Owner)
+            } else {
+                (ResetScope::PublishedBy(sender), Authority::Scoped)
+            };
+            let action = ControlAction::ResetRunners {
+                receivers: reset.receivers.clone(),
+                scope,
+            };
+            Some(Ok((action, authority)))
+        }
+        _ => None,
+    }
+}
+
+/// Authenticates control events, caching transaction senders.
+#[derive(Debug, Clone)]
+pub struct ControlPlane {
+    client: Client,
+    senders: HashMap<H256, Address>,
+}
+
+impl ControlPlane {
+    pub fn new(client: &Client) -> Self {
+        ControlPlane {
+            client: client.clone(),
+            senders: HashMap::new(),
+        }
+    }
+
+    /// The sender of the transaction that emitted a log.
+    pub async fn sender(&mut self, transaction_hash: H256) -> Result<Address, anyhow::Error> {
+        if let Some(sender) = self.senders.get(&transaction_hash) {
+            return Ok(*sender);
+        }
+        let sender = self
+            .client
+            .eth
+            .transaction(TransactionId::Hash(transaction_hash))
+            .await?
+            .and_then(|tx| tx.from)
+            .ok_or_else(|| anyhow::anyhow!("transaction {:?} not found", transaction_hash))?;
+        self.senders.insert(transaction_hash, sender);
+        Ok(sender)
+    }
+
+    /// Authenticates a `StopTask` or `ResetRunners` log against the owner
+    /// and publisher as of the log's block; other logs give `None`.
+    pub async fn authenticate(
+        &mut self,
+        log: &DepLog,
+    ) -> Result<Option<ControlMessage>, anyhow::Error> {
+        let task_id = match &log.event {
+            DepEvent::StopTask(stop) => Some(stop.task_id),
+            DepEvent::ResetRunners(_) => None,
+            _ => return Ok(None),
+        };
+        let sender = self.sender(log.transaction_hash).await?;
+        let at_block = self.client.at_block(BlockId::Number(BlockNumber::Number(
+            log.block_number.into(),
+        )));
+        let (owner,) = at_block.owner().await?;
+        let publisher = match task_id {
+            Some(task_id) if task_id <= u64::MAX.into() => {
+                let info = at_block.calls().task_info(task_id.low_u64()).await?;
+                Some(info.publisher).filter(|publisher| !publisher.is_zero())
+            }
+            _ => None,
+        };
+        let message = match authorize(&log.event, sender, owner, publisher) {
+            Some(Ok((action, authority))) => ControlMessage::Authorized {
+                action,
+                sender,
+                authority,
+                block_number: log.block_number,
+                transaction_hash: log.transaction_hash,
+            },
+            Some(Err(reason)) => ControlMessage::Rejected {
+                event: log.event.clone(),
+                sender,
+                reason,
+                block_number: log.block_number,
+                transaction_hash: log.transaction_hash,
+            },
+            None => return Ok(None),
+        };
+        Ok(Some(message))
+    }
+}
+
+/// Authenticated control messages for a runner, from a block on.
+pub struct ControlStream {
+    watcher: EventWatcher,
+    plane: ControlPlane,
+    runner: Address,
+    /// Logs read but not yet authenticated, in order.
+    unauthenticated: VecDeque<DepLog>,
+    /// Messages authenticated by a poll that failed before returning them.
+    ready: Vec<ControlMessage>,
+}
+
+impl ControlStream {
+    /// Follows control events addressed to `runner`.
+    pub fn new(client: &Client, runner: Address, from_block: u64) -> Self {
+        ControlStream {
+            watcher: EventWatcher::new(client, from_block),
+            plane: ControlPlane::new(client),
+            runner,
+            unauthenticated: VecDeque::new(),
+            ready: vec![],
+        }
+    }
+
+    /// The block to resume from so that no log still to be authenticated is
+    /// missed.
+    pub fn next_block(&self) -> u64 {
+        match self.unauthenticated.front() {
+            Some(log) => log.block_number,
+            None => self.watcher.next_block(),
+        }
+    }
+
+    /// Authorized commands addressed to the runner and every rejected
+    /// message since the last poll. A log that fails to authenticate is
+    /// retried first on the next poll, and the messages authenticated before
+    /// it are returned then.
+    pub async fn poll(&mut self) -> Result<Vec<ControlMessage>, anyhow::Error> {
+        self.unauthenticated
+            .extend(self.watcher.poll().await?.events());
+        while let Some(log) = self.unauthenticated.front() {
+            match self.plane.authenticate(log).await? {
+                Some(ControlMessage::Authorized { ref action, .. })
+                    if !action.addresses(self.runner) => {}
+                Some(message) => self.ready.push(message),
+                None => {}
+            }
+            self.unauthenticated.pop_front();
+        }
+        Ok(std::mem::take(&mut self.ready))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::{ResetRunners, StopTask};
+
+    #[test]
+    fn test_authorize_stop() {
+        let owner = Address::from_low_u64_be(1);
+        let publisher = Address::from_low_u64_be(2);
+        let stranger = Address::from_low_u64_be(3);
+        let stop = DepEvent::StopTask(StopTask { task_id: 7.into() });
+        let action = ControlAction::StopTask { task_id: 7 };
+
+        assert_eq!(
+            authorize(&stop, publisher, owner, Some(publisher)),
+            Some(Ok((action.clone(), Authority::Publisher)))
+        );
+        assert_eq!(
+            authorize(&stop, owner, owner, Some(publisher)),
+            Some(Ok((action, Authority::Owner)))
+        );
+        assert!(matches!(
+            authorize(&stop, stranger, owner, Some(publisher)),
+            Some(Err(_))
+        ));
+        assert!(matches!(authorize(&stop, owner, owner, None), Some(Err(_))));
+        let huge = DepEvent::StopTask(StopTask {
+            task_id: web3::types::U256::MAX,
+        });
+        assert!(matches!(
+            authorize(&huge, owner, owner, Some(publisher)),
+            Some(Err(_))
+        ));
+    }
+
+    #[test]
+    fn test_authorize_reset() {
+        let owner = Address::from_low_u64_be(1);
+        let runner = Address::from_low_u64_be(4);
+        let other = Address::from_low_u64_be(5);
+        let reset = DepEvent::ResetRunners(ResetRunners {
+            receivers: vec![runner],
+        });
+
+        let (action, authority) = authorize(&reset, owner, owner, None).unwrap().unwrap();
+        assert_eq!(authority, Authority::Owner);
+        assert!(matches!(
+            action,
+            ControlAction::ResetRunners {
+                scope: ResetScope::All,
+                ..
+            }
+        ));
+        assert!(action.addresses(runner));
+        assert!(!action.addresses(other));
+
+        let (action, authority) = authorize(&reset, other, owner, None).unwrap().unwrap();
+        assert_eq!(authority, Authority::Scoped);
+        assert!(matches!(
+            action,
+            ControlAction::ResetRunners {
+                scope: ResetScope::PublishedBy(sender),
+                ..
+            } if sender == other
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream_keeps_unauthenticated_logs() {
+        // never reaches a node, so every poll fails
+        let client = Client::with_abi_version(
+            "http://127.0.0.1:1",
+            "9397AA12576cEc2A37C60f76d2FB31b31b5E5c7F",
+            crate::abi::AbiVersion::LATEST,
+            ethers::signers::LocalWallet::new(&mut rand::thread_rng()),
+        )
+        .unwrap();
+        let mut stream = ControlStream::new(&client, Address::from_low_u64_be(4), 10);
+        assert_eq!(stream.next_block(), 10);
+        stream.unauthenticated.push_back(DepLog {
+            block_number: 3,
+            transaction_hash: H256::zero(),
+            log_index: 0,
+            event: DepEvent::StopTask(StopTask { task_id: 7.into() }),
+        });
+        assert!(stream.poll().await.is_err());
+        assert_eq!(stream.unauthenticated.len(), 1);
+        assert_eq!(stream.next_block(), 3);
+    }
+}
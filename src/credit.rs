@@ -0,0 +1,112 @@
+//! Node credit scores from the dispatch precompile.
+//!
+//! `raceSubIndexForTask` calls `get_credit_score(msg.sender)` on the
+//! precompile at `0x…0406` and reverts with "Low credit score, no right to
+//! enforce" when the score is below `creditThreshold`. These reads make the
+//! same call so the check can be run before racing.
+
+use crate::Client;
+use anyhow::Context;
+use web3::ethabi::{self, ParamType, Token};
+use web3::types::{Address, Bytes, CallRequest};
+
+/// The dispatch precompile `raceSubIndexForTask` asks for credit scores.
+pub const DISPATCH: Address = web3::types::H160([
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x04, 0x06,
+]);
+
+/// A node's credit score next to the contract's threshold, read from the
+/// same block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CreditCheck {
+    pub score: u64,
+    pub threshold: u64,
+}
+
+impl CreditCheck {
+    /// Whether `raceSubIndexForTask` would pass its credit check.
+    pub fn eligible(&self) -> bool {
+        self.score >= self.threshold
+    }
+}
+
+/// Decodes precompile output the way `_toUint64` does: it loads the first
+/// 32-byte word and keeps its low 8 bytes. Output shorter than a word is
+/// padded with zeros, as the fresh memory after it would be.
+pub fn decode_credit_score(output: &[u8]) -> u64 {
+    let mut word = [0u8; 32];
+    let len = output.len().min(32);
+    word[..len].copy_from_slice(&output[..len]);
+    u64::from_be_bytes(word[24..].try_into().expect("8 bytes"))
+}
+
+impl Client {
+    /// The credit score `raceSubIndexForTask` would see for `address`. The
+    /// call runs in a `call` span like contract reads do.
+    pub async fn credit_score(&self, address: Address) -> Result<u64, anyhow::Error> {
+        let mut data = ethabi::short_signature("get_credit_score", &[ParamType::Address]).to_vec();
+        data.extend(ethabi::encode(&[Token::Address(address)]));
+        let call = async {
+            self.eth
+                .call(
+                    CallRequest {
+                        from: Some(address),
+                        to: Some(DISPATCH),
+                        data: Some(Bytes(data)),
+                        ..CallRequest::default()
+                    },
+                    self.block,
+                )
+                .await
+                .context("get_credit_score not ok")
+        };
+        let output = self
+            .traced_call("get_credit_score", &[Token::Address(address)], call)
+            .await?;
+        Ok(decode_credit_score(&output.0))
+    }
+
+    /// The score and threshold `raceSubIndexForTask` would compare.
+    pub async fn credit_check(&self, address: Address) -> Result<CreditCheck, anyhow::Error> {
+        let client = match self.block() {
+            Some(_) => self.clone(),
+            None => self.snapshot().await?,
+        };
+        Ok(CreditCheck {
+            score: client.credit_score(address).await?,
+            threshold: client.credit_threshold().await?.0,
+        })
+    }
+
+    /// Whether `address` may race for tasks.
+    pub async fn is_credit_eligible(&self, address: Address) -> Result<bool, anyhow::Error> {
+        Ok(self.credit_check(address).await?.eligible())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_credit_score() {
+        let word = ethabi::encode(&[Token::Uint(742.into())]);
+        assert_eq!(decode_credit_score(&word), 742);
+        // only the low 8 bytes of the first word count
+        let mut wide = word.clone();
+        wide[0] = 0xff;
+        wide.extend([0xff; 32]);
+        assert_eq!(decode_credit_score(&wide), 742);
+        assert_eq!(decode_credit_score(&[]), 0);
+        assert_eq!(decode_credit_score(&[1; 8]), 0);
+        assert_eq!(
+            format!("{:?}", DISPATCH),
+            "0x0000000000000000000000000000000000000406"
+        );
+        assert!(!CreditCheck {
+            score: 9,
+            threshold: 10
+        }
+        .eligible());
+    }
+}
@@ -0,0 +1,220 @@
+//! Contract days.
+//!
+//! The contract counts days as `block.timestamp / 1 days`, i.e. whole UTC
+//! days since the unix epoch, and records its deployment day in `startDay`.
+//! `DepDay` wraps that number so that conversions to dates, timestamps and
+//! blocks are done in one place.
+
+use crate::Client;
+use chrono::{NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A contract day: whole UTC days since 1970-01-01.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct DepDay(pub u64);
+
+impl DepDay {
+    pub const SECS: u64 = 86_400;
+
+    /// The day containing unix time `timestamp`, as `getCurrentDay` computes it.
+    pub fn from_timestamp(timestamp: u64) -> Self {
+        DepDay(timestamp / DepDay::SECS)
+    }
+
+    /// The unix time the day starts at.
+    pub fn start_timestamp(&self) -> u64 {
+        self.0 * DepDay::SECS
+    }
+
+    /// The unix time the next day starts at.
+    pub fn end_timestamp(&self) -> u64 {
+        (self.0 + 1) * DepDay::SECS
+    }
+
+    pub fn from_date(date: NaiveDate) -> Result<Self, anyhow::Error> {
+        let days = date.signed_duration_since(epoch()).num_days();
+        if days < 0 {
+            anyhow::bail!("{} is before the unix epoch", date);
+        }
+        Ok(DepDay(days as u64))
+    }
+
+    /// The day's date, or `None` past the last date chrono represents.
+    pub fn date(&self) -> Option<NaiveDate> {
+        let millis = i64::try_from(self.0)
+            .ok()?
+            .checked_mul(DepDay::SECS as i64 * 1000)?;
+        epoch().checked_add_signed(chrono::Duration::milliseconds(millis))
+    }
+
+    pub fn next(&self) -> DepDay {
+        DepDay(self.0 + 1)
+    }
+
+    pub fn prev(&self) -> Option<DepDay> {
+        self.0.checked_sub(1).map(DepDay)
+    }
+
+    /// Whole days from `earlier` to this day, or `None` if `earlier` is later.
+    pub fn days_since(&self, earlier: DepDay) -> Option<u64> {
+        self.0.checked_sub(earlier.0)
+    }
+
+    /// The Monday of the day's week.
+    /// The Monday of the day's week; days before the first Monday after the
+    /// epoch map to day 0.
+    pub fn week_start(&self) -> DepDay {
+        let weekday = self.weekday().num_days_from_monday() as u64;
+        DepDay(self.0.saturating_sub(weekday))
+    }
+
+    pub fn weekday(&self) -> Weekday {
+        // the epoch was a Thursday
+        match (self.0 % 7 + 3) % 7 {
+            0 => Weekday::Mon,
+            1 => Weekday::Tue,
+            2 => Weekday::Wed,
+            3 => Weekday::Thu,
+            4 => Weekday::Fri,
+            5 => Weekday::Sat,
+            _ => Weekday::Sun,
+        }
+    }
+
+    /// Every day from `from` through `to`.
+    pub fn range(from: DepDay, to: DepDay) -> impl DoubleEndedIterator<Item = DepDay> {
+        (from.0..=to.0).map(DepDay)
+    }
+}
+
+fn epoch() -> NaiveDate {
+    NaiveDate::from_ymd(1970, 1, 1)
+}
+
+impl From<u64> for DepDay {
+    fn from(day: u64) -> Self {
+        DepDay(day)
+    }
+}
+
+impl From<DepDay> for u64 {
+    fn from(day: DepDay) -> Self {
+        day.0
+    }
+}
+
+impl From<NaiveDate> for DepDay {
+    /// Dates before the epoch map to day 0.
+    fn from(date: NaiveDate) -> Self {
+        DepDay::from_date(date).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for DepDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.date() {
+            Some(date) => write!(f, "{}", date.format("%Y-%m-%d")),
+            None => write!(f, "day {}", self.0),
+        }
+    }
+}
+
+impl FromStr for DepDay {
+    type Err = anyhow::Error;
+
+    /// Accepts a `YYYY-MM-DD` date or a raw day number that has a date.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.parse::<u64>() {
+            Ok(day) if DepDay(day).date().is_none() => {
+                anyhow::bail!("day {} is out of range", day)
+            }
+            Ok(day) => Ok(DepDay(day)),
+            Err(_) => DepDay::from_date(NaiveDate::parse_from_str(s, "%Y-%m-%d")?),
+        }
+    }
+}
+
+impl Client {
+    /// The current contract day.
+    pub async fn current_day(&self) -> Result<DepDay, anyhow::Error> {
+        Ok(DepDay(self.calls().get_current_day().await?))
+    }
+
+    /// The day the contract was deployed.
+    pub async fn deployment_day(&self) -> Result<DepDay, anyhow::Error> {
+        Ok(DepDay(self.calls().start_day().await?))
+    }
+
+    /// Days from `startDay` to `day`, or `None` for days before deployment.
+    pub async fn days_since_start(&self, day: DepDay) -> Result<Option<u64>, anyhow::Error> {
+        Ok(day.days_since(self.deployment_day().await?))
+    }
+
+    /// The contract day a block was produced on.
+    pub async fn day_of_block(&self, block: u64) -> Result<DepDay, anyhow::Error> {
+        Ok(DepDay::from_timestamp(self.block_timestamp(block).await?))
+    }
+
+    /// The blocks produced on `day`, or `None` if the day has not started or
+    /// has no blocks yet. The range of the current day ends at the head.
+    pub async fn blocks_of_day(&self, day: DepDay) -> Result<Option<(u64, u64)>, anyhow::Error> {
+        let from_block = match self.first_block_at(day.start_timestamp()).await? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        let to_block = match self.first_block_at(day.end_timestamp()).await? {
+            Some(next_day) if next_day == from_block => return Ok(None),
+            Some(next_day) => next_day - 1,
+            None => self.eth.block_number().await?.as_u64(),
+        };
+        Ok(Some((from_block, to_block)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dates() {
+        let day = DepDay::from_date(NaiveDate::from_ymd(2022, 8, 15)).unwrap();
+        assert_eq!(day, DepDay(19219));
+        assert_eq!(day.to_string(), "2022-08-15");
+        assert_eq!("2022-08-15".parse::<DepDay>().unwrap(), day);
+        assert_eq!("19219".parse::<DepDay>().unwrap(), day);
+        assert!(DepDay::from_date(NaiveDate::from_ymd(1969, 12, 31)).is_err());
+        assert!("99999999999".parse::<DepDay>().is_err());
+        assert_eq!(DepDay(u64::MAX).date(), None);
+        assert_eq!(DepDay(u64::MAX).to_string(), format!("day {}", u64::MAX));
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let day = DepDay(19219);
+        assert_eq!(DepDay::from_timestamp(day.start_timestamp()), day);
+        assert_eq!(DepDay::from_timestamp(day.end_timestamp() - 1), day);
+        assert_eq!(DepDay::from_timestamp(day.end_timestamp()), day.next());
+        assert_eq!(day.days_since(DepDay(19200)), Some(19));
+        assert_eq!(DepDay(19200).days_since(day), None);
+    }
+
+    #[test]
+    fn test_weeks_and_ranges() {
+        assert_eq!(DepDay(19219).weekday(), Weekday::Mon);
+        assert_eq!(DepDay(19225).week_start(), DepDay(19219));
+        assert_eq!(DepDay(19226).week_start(), DepDay(19226));
+        assert_eq!(DepDay(0).weekday(), Weekday::Thu);
+        assert_eq!(DepDay(0).week_start(), DepDay(0));
+        assert_eq!(DepDay(2).week_start(), DepDay(0));
+        assert_eq!(DepDay(4).week_start(), DepDay(4));
+        let days: Vec<_> = DepDay::range(DepDay(3), DepDay(5)).collect();
+        assert_eq!(days, vec![DepDay(3), DepDay(4), DepDay(5)]);
+        assert_eq!(DepDay::range(DepDay(5), DepDay(3)).count(), 0);
+    }
+}
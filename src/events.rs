@@ -0,0 +1,362 @@
+//! Polling watcher for DEP contract logs and calls.
+//!
+//! Several contract setters (`setRaceTimeout`, `setAddressWhitelist`, ...)
+//! change state without emitting an event, so the watcher can optionally
+//! index successful transactions sent to the contract as well.
+
+use crate::Client;
+use serde::{Deserialize, Serialize};
+use web3::ethabi::Token;
+use web3::futures::future;
+use web3::futures::stream::{self, StreamExt, TryStreamExt};
+use web3::types::{Address, BlockId, BlockNumber, FilterBuilder, Log, H256, U64};
+
+pub use crate::bindings::events::*;
+
+/// Upper bound on the number of blocks fetched by a single poll.
+pub(crate) const MAX_BLOCK_RANGE: u64 = 5000;
+
+/// Blocks fetched at once when indexing contract calls.
+const BLOCK_FETCH_CONCURRENCY: usize = 16;
+
+/// A successful transaction sent to the DEP contract, decoded against its ABI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContractCall {
+    pub block_number: u64,
+    pub transaction_hash: H256,
+    pub from: Address,
+    pub function: String,
+    pub inputs: Vec<Token>,
+}
+
+/// A decoded contract event together with where it was emitted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DepLog {
+    pub block_number: u64,
+    pub transaction_hash: H256,
+    pub log_index: u64,
+    pub event: DepEvent,
+}
+
+impl DepLog {
+    /// Decodes a contract log, or returns `None` for logs of other events.
+    pub fn decode(log: &Log) -> Result<Option<Self>, anyhow::Error> {
+        Ok(DepEvent::decode(log)?.map(|event| DepLog {
+            block_number: log.block_number.unwrap_or_default().as_u64(),
+            transaction_hash: log.transaction_hash.unwrap_or_default(),
+            log_index: log.log_index.unwrap_or_default().as_u64(),
+            event,
+        }))
+    }
+}
+
+/// Everything the watcher saw in `from_block..=to_block`.
+#[derive(Debug, Clone, Default)]
+pub struct EventBatch {
+    pub from_block: u64,
+    pub to_block: u64,
+    pub logs: Vec<Log>,
+    pub calls: Vec<ContractCall>,
+}
+
+impl EventBatch {
+    pub fn is_empty(&self) -> bool {
+        self.logs.is_empty() && self.calls.is_empty()
+    }
+
+    /// Decodes the batch's logs, skipping any that do not match the ABI.
+    pub fn events(&self) -> Vec<DepLog> {
+        self.logs
+            .iter()
+            .filter_map(|log| match DepLog::decode(log) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    log::warn!("skipping undecodable log {:?}: {}", log.transaction_hash, e);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Follows the chain head and returns new contract activity on each poll.
+#[derive(Debug, Clone)]
+pub struct EventWatcher {
+    client: Client,
+    next_block: u64,
+    include_calls: bool,
+}
+
+impl EventWatcher {
+    pub fn new(client: &Client, from_block: u64) -> Self {
+        EventWatcher {
+            client: client.clone(),
+            next_block: from_block,
+            include_calls: false,
+        }
+    }
+
+    /// Also index transactions sent to the contract. This fetches every block
+    /// in the polled range, so it is considerably more expensive than logs.
+    pub fn with_calls(mut self, include_calls: bool) -> Self {
+        self.include_calls = include_calls;
+        self
+    }
+
+    /// The first block the next poll will look at.
+    pub fn next_block(&self) -> u64 {
+        self.next_block
+    }
+
+    /// Fetches activity from the last polled block up to the current head,
+    /// at most `MAX_BLOCK_RANGE` blocks at a time.
+    pub async fn poll(&mut self) -> Result<EventBatch, anyhow::Error> {
+        let head = self.client.eth.block_number().await?.as_u64();
+        if self.next_block > head {
+            return Ok(EventBatch {
+                from_block: self.next_block,
+                to_block: head,
+                ..EventBatch::default()
+            });
+        }
+        let from_block = self.next_block;
+        let to_block = head.min(from_block + MAX_BLOCK_RANGE - 1);
+        let logs = self.client.logs(from_block, to_block).await?;
+        let calls = if self.include_calls {
+            self.client.contract_calls(from_block, to_block).await?
+        } else {
+            vec![]
+        };
+        self.next_block = to_block + 1;
+        Ok(EventBatch {
+            from_block,
+            to_block,
+            logs,
+            calls,
+        })
+    }
+}
+
+impl Client {
+    /// Returns the ABI name of the event a contract log was emitted for.
+    pub fn event_name(&self, log: &Log) -> Option<&str> {
+        let topic = log.topics.first()?;
+        self.contract
+            .abi()
+            .events()
+            .find(|event| event.signature() == *topic)
+            .map(|event| event.name.as_str())
+    }
+
+    /// Fetches the contract logs emitted in `from_block..=to_block`.
+    pub async fn logs(&self, from_block: u64, to_block: u64) -> Result<Vec<Log>, anyhow::Error> {
+        let filter = FilterBuilder::default()
+            .address(vec![self.contract.address()])
+            .from_block(BlockNumber::Number(from_block.into()))
+            .to_block(BlockNumber::Number(to_block.into()))
+            .build();
+        Ok(self.eth.logs(filter).await?)
+    }
+
+    /// Fetches and decodes the contract events in `from_block..=to_block`,
+    /// in chunks of at most `MAX_BLOCK_RANGE` blocks.
+    pub async fn events_between(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<DepLog>, anyhow::Error> {
+        let mut events = vec![];
+        let mut start = from_block;
+        while start <= to_block {
+            let end = to_block.min(start + MAX_BLOCK_RANGE - 1);
+            let batch = EventBatch {
+                from_block: start,
+                to_block: end,
+                logs: self.logs(start, end).await?,
+                calls: vec![],
+            };
+            events.extend(batch.events());
+            start = end + 1;
+        }
+        Ok(events)
+    }
+
+    /// The number of the latest block.
+    pub async fn block_number(&self) -> Result<u64, anyhow::Error> {
+        Ok(self.eth.block_number().await?.as_u64())
+    }
+
+    /// The first block with a timestamp at or after `timestamp`, or `None`
+    /// if the head is older.
+    pub async fn first_block_at(&self, timestamp: u64) -> Result<Option<u64>, anyhow::Error> {
+        let head = self.eth.block_number().await?.as_u64();
+        if self.block_timestamp(head).await? < timestamp {
+            return Ok(None);
+        }
+        let (mut low, mut high) = (0, head);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.block_timestamp(middle).await? < timestamp {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        Ok(Some(low))
+    }
+
+    pub async fn block_timestamp(&self, number: u64) -> Result<u64, anyhow::Error> {
+        let block = self
+            .eth
+            .block(BlockId::Number(BlockNumber::Number(number.into())))
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("block {} not found", number))?;
+        Ok(block.timestamp.as_u64())
+    }
+
+    /// Fetches and decodes the successful transactions sent to the contract
+    /// in `from_block..=to_block`, `BLOCK_FETCH_CONCURRENCY` blocks at a time.
+    pub async fn contract_calls(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<ContractCall>, anyhow::Error> {
+        let blocks: Vec<Vec<ContractCall>> = stream::iter(from_block..=to_block)
+            .map(|number| self.block_calls(number))
+            .buffered(BLOCK_FETCH_CONCURRENCY)
+            .try_collect()
+            .await?;
+        Ok(blocks.into_iter().flatten().collect())
+    }
+
+    /// The successful contract calls in one block, in transaction order.
+    async fn block_calls(&self, number: u64) -> Result<Vec<ContractCall>, anyhow::Error> {
+        let block = self
+            .eth
+            .block_with_txs(BlockId::Number(BlockNumber::Number(number.into())))
+            .await?;
+        let block = match block {
+            Some(block) => block,
+            None => return Ok(vec![]),
+        };
+        let mut calls = vec![];
+        for tx in block.transactions {
+            if tx.to != Some(self.contract.address()) || tx.input.0.len() < 4 {
+                continue;
+            }
+            let (selector, data) = tx.input.0.split_at(4);
+            let function = match self
+                .contract
+                .abi()
+                .functions()
+                .find(|function| function.short_signature() == selector)
+            {
+                Some(function) => function,
+                None => continue,
+            };
+            let inputs = match function.decode_input(data) {
+                Ok(inputs) => inputs,
+                Err(_) => continue,
+            };
+            calls.push(ContractCall {
+                block_number: number,
+                transaction_hash: tx.hash,
+                from: tx.from.unwrap_or_default(),
+                function: function.name.clone(),
+                inputs,
+            });
+        }
+        let receipts = future::try_join_all(
+            calls
+                .iter()
+                .map(|call| self.eth.transaction_receipt(call.transaction_hash)),
+        )
+        .await?;
+        Ok(calls
+            .into_iter()
+            .zip(receipts)
+            .filter(|(_, receipt)| {
+                receipt.as_ref().and_then(|receipt| receipt.status) == Some(U64::from(1))
+            })
+            .map(|(call, _)| call)
+            .collect())
+    }
+}
+
+/// Builds a contract log as a node would return it, for tests.
+#[cfg(test)]
+pub(crate) fn raw_log(block_number: u64, topics: Vec<H256>, data: Vec<u8>) -> Log {
+    Log {
+        address: Address::zero(),
+        topics,
+        data: web3::types::Bytes(data),
+        block_hash: None,
+        block_number: Some(U64::from(block_number)),
+        transaction_hash: Some(H256::from_low_u64_be(block_number)),
+        transaction_index: None,
+        log_index: Some(0.into()),
+        transaction_log_index: None,
+        log_type: None,
+        removed: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use web3::ethabi;
+    use web3::types::U256;
+
+    #[test]
+    fn test_decode_task_published() {
+        let receiver = Address::from_low_u64_be(7);
+        let data = ethabi::encode(&[
+            Token::Uint(U256::from(3)),
+            Token::String("xbgxwh/oracle_price:1.0.3".to_string()),
+            Token::String("{}".to_string()),
+            Token::Uint(U256::from(2)),
+            Token::Array(vec![Token::Address(receiver)]),
+            Token::Uint(U256::from(600)),
+        ]);
+        let log = raw_log(42, vec![TaskPublished::SIGNATURE], data);
+        let decoded = DepLog::decode(&log).unwrap().unwrap();
+        assert_eq!(decoded.block_number, 42);
+        assert_eq!(
+            decoded.event,
+            DepEvent::TaskPublished(TaskPublished {
+                task_id: 3,
+                url: "xbgxwh/oracle_price:1.0.3".to_string(),
+                options: "{}".to_string(),
+                max_run_num: U256::from(2),
+                receivers: vec![receiver],
+                maintain_blocks: 600,
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_indexed_role_event() {
+        let role = H256::repeat_byte(1);
+        let account = Address::from_low_u64_be(2);
+        let sender = Address::from_low_u64_be(3);
+        let log = raw_log(
+            1,
+            vec![
+                RoleGranted::SIGNATURE,
+                role,
+                H256::from(account),
+                H256::from(sender),
+            ],
+            vec![],
+        );
+        assert_eq!(
+            DepEvent::decode(&log).unwrap(),
+            Some(DepEvent::RoleGranted(RoleGranted {
+                role,
+                account,
+                sender
+            }))
+        );
+        assert_eq!(DepEvent::decode(&raw_log(1, vec![], vec![])).unwrap(), None);
+    }
+}
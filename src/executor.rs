@@ -0,0 +1,353 @@
+//! Executors that run the workloads of raced tasks.
+//!
+//! `TaskPublished` only carries an image `url` and a free-form `options`
+//! string; an executor turns them into a running workload. The runner starts
+//! a workload once it wins a race, polls it until it finishes or its
+//! `maintainBlocks` lifetime runs out, and stops it early on `StopTask` or
+//! `ResetRunners`.
+
+use crate::options::TaskOptions;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use tokio::process::{Child, Command};
+
+/// Seconds per block assumed by the contract's `getTaskRemainingTime`.
+pub const BLOCK_TIME_SECS: u64 = 5;
+
+/// A task this node won a race for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssignedTask {
+    pub task_id: u64,
+    pub url: String,
+    pub options: String,
+    pub start_time: u64,
+    pub maintain_blocks: u64,
+}
+
+impl AssignedTask {
+    /// The unix time at which the task's `maintainBlocks` run out, or `None`
+    /// for tasks without a maintained lifetime.
+    pub fn expires_at(&self) -> Option<u64> {
+        match self.maintain_blocks {
+            0 => None,
+            blocks => Some(self.start_time + blocks * BLOCK_TIME_SECS),
+        }
+    }
+
+    /// The task's options, decoded leniently since historical tasks were
+    /// published with free-form strings.
+    pub fn parsed_options(&self) -> TaskOptions {
+        TaskOptions::decode_lenient(&self.options)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WorkloadStatus {
+    Running,
+    Succeeded,
+    Failed(String),
+    /// The executor has no workload for the task, e.g. after a restart.
+    Unknown,
+}
+
+/// Starts, observes and stops task workloads. Workloads are keyed by task
+/// id, since a node runs at most one sub-task per task.
+#[async_trait]
+pub trait TaskExecutor: Send + Sync + 'static {
+    async fn start(&self, task: &AssignedTask) -> Result<(), anyhow::Error>;
+
+    async fn poll(&self, task_id: u64) -> Result<WorkloadStatus, anyhow::Error>;
+
+    /// Stops a workload. Stopping an unknown task is not an error.
+    async fn stop(&self, task_id: u64) -> Result<(), anyhow::Error>;
+}
+
+/// What a publisher's `TaskOptions` may pass to a workload process.
+///
+/// The options are chosen by whoever publishes the task, so by default none
+/// of their env vars or args reach the operator's host. The operator allows
+/// env var names and arg patterns one by one; a task asking for anything
+/// else is refused.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OptionsPolicy {
+    env: BTreeSet<String>,
+    args: Vec<String>,
+}
+
+impl OptionsPolicy {
+    /// Passes nothing through.
+    pub fn new() -> Self {
+        OptionsPolicy::default()
+    }
+
+    pub fn allow_env(mut self, name: &str) -> Self {
+        self.env.insert(name.to_string());
+        self
+    }
+
+    /// Allows args equal to `pattern`, or starting with what precedes a
+    /// trailing `*`, e.g. `--threads=*`.
+    pub fn allow_arg(mut self, pattern: &str) -> Self {
+        self.args.push(pattern.to_string());
+        self
+    }
+
+    fn allows_arg(&self, arg: &str) -> bool {
+        self.args
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => arg.starts_with(prefix),
+                None => arg == pattern,
+            })
+    }
+
+    /// Fails on the first env var or arg the operator did not allow.
+    pub fn check(&self, options: &TaskOptions) -> Result<(), anyhow::Error> {
+        if let Some(name) = options.env.keys().find(|name| !self.env.contains(*name)) {
+            anyhow::bail!("env var {:?} is not allowed", name);
+        }
+        if let Some(arg) = options.args.iter().find(|arg| !self.allows_arg(arg)) {
+            anyhow::bail!("arg {:?} is not allowed", arg);
+        }
+        Ok(())
+    }
+}
+
+/// Runs each workload as a local process, never through a shell.
+///
+/// A configured argument that is exactly `{task_id}` or `{url}` is replaced
+/// by that value as a single argument; placeholders inside longer arguments
+/// are left alone. The values, and the raw options string, are exported as
+/// `DEP_TASK_ID`, `DEP_TASK_URL` and `DEP_TASK_OPTIONS`. For example
+/// `docker run --rm {url}`. Env vars and args from the parsed `TaskOptions`
+/// are only passed when the `OptionsPolicy` allows them.
+#[derive(Debug)]
+pub struct LocalProcessExecutor {
+    program: String,
+    args: Vec<String>,
+    policy: OptionsPolicy,
+    children: Mutex<HashMap<u64, Child>>,
+}
+
+impl LocalProcessExecutor {
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        LocalProcessExecutor {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            policy: OptionsPolicy::default(),
+            children: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Lets the task options allowed by `policy` through.
+    pub fn with_options_policy(mut self, policy: OptionsPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+}
+
+#[async_trait]
+impl TaskExecutor for LocalProcessExecutor {
+    async fn start(&self, task: &AssignedTask) -> Result<(), anyhow::Error> {
+        if task.url.starts_with('-') {
+            anyhow::bail!("image url {:?} looks like a flag", task.url);
+        }
+        let options = task.parsed_options();
+        self.policy.check(&options)?;
+        let task_id = task.task_id.to_string();
+        let substitute = |arg: &String| match arg.as_str() {
+            "{task_id}" => task_id.clone(),
+            "{url}" => task.url.clone(),
+            _ => arg.clone(),
+        };
+        let child = Command::new(&self.program)
+            .args(self.args.iter().map(substitute))
+            .args(&options.args)
+            .envs(&options.env)
+            .env("DEP_TASK_ID", &task_id)
+            .env("DEP_TASK_URL", &task.url)
+            .env("DEP_TASK_OPTIONS", &task.options)
+            .kill_on_drop(true)
+            .spawn()?;
+        self.children.lock().unwrap().insert(task.task_id, child);
+        Ok(())
+    }
+
+    async fn poll(&self, task_id: u64) -> Result<WorkloadStatus, anyhow::Error> {
+        let mut children = self.children.lock().unwrap();
+        let child = match children.get_mut(&task_id) {
+            Some(child) => child,
+            None => return Ok(WorkloadStatus::Unknown),
+        };
+        Ok(match child.try_wait()? {
+            None => WorkloadStatus::Running,
+            Some(status) if status.success() => WorkloadStatus::Succeeded,
+            Some(status) => WorkloadStatus::Failed(format!("process exited with {}", status)),
+        })
+    }
+
+    async fn stop(&self, task_id: u64) -> Result<(), anyhow::Error> {
+        let child = self.children.lock().unwrap().remove(&task_id);
+        if let Some(mut child) = child {
+            child.kill().await?;
+        }
+        Ok(())
+    }
+}
+
+/// An in-memory executor for tests. Started workloads stay `Running` until
+/// `finish` is called.
+#[derive(Debug, Default)]
+pub struct MockExecutor {
+    workloads: Mutex<HashMap<u64, WorkloadStatus>>,
+    started: Mutex<Vec<u64>>,
+    stopped: Mutex<Vec<u64>>,
+    failures: Mutex<HashMap<u64, String>>,
+}
+
+impl MockExecutor {
+    pub fn new() -> Self {
+        MockExecutor::default()
+    }
+
+    /// Sets the status the next polls of `task_id` will report.
+    pub fn finish(&self, task_id: u64, status: WorkloadStatus) {
+        self.workloads.lock().unwrap().insert(task_id, status);
+    }
+
+    /// Makes every later call for `task_id` return an error with `message`.
+    pub fn fail(&self, task_id: u64, message: &str) {
+        self.failures
+            .lock()
+            .unwrap()
+            .insert(task_id, message.to_string());
+    }
+
+    fn check(&self, task_id: u64) -> Result<(), anyhow::Error> {
+        match self.failures.lock().unwrap().get(&task_id) {
+            Some(message) => Err(anyhow::anyhow!("{}", message)),
+            None => Ok(()),
+        }
+    }
+
+    /// Task ids in the order their workloads were started.
+    pub fn started(&self) -> Vec<u64> {
+        self.started.lock().unwrap().clone()
+    }
+
+    /// Task ids in the order their workloads were stopped.
+    pub fn stopped(&self) -> Vec<u64> {
+        self.stopped.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl TaskExecutor for MockExecutor {
+    async fn start(&self, task: &AssignedTask) -> Result<(), anyhow::Error> {
+        self.check(task.task_id)?;
+        self.started.lock().unwrap().push(task.task_id);
+        self.workloads
+            .lock()
+            .unwrap()
+            .insert(task.task_id, WorkloadStatus::Running);
+        Ok(())
+    }
+
+    async fn poll(&self, task_id: u64) -> Result<WorkloadStatus, anyhow::Error> {
+        self.check(task_id)?;
+        Ok(self
+            .workloads
+            .lock()
+            .unwrap()
+            .get(&task_id)
+            .cloned()
+            .unwrap_or(WorkloadStatus::Unknown))
+    }
+
+    async fn stop(&self, task_id: u64) -> Result<(), anyhow::Error> {
+        self.check(task_id)?;
+        if self.workloads.lock().unwrap().remove(&task_id).is_some() {
+            self.stopped.lock().unwrap().push(task_id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn task(task_id: u64) -> AssignedTask {
+        AssignedTask {
+            task_id,
+            url: "xbgxwh/oracle_price:1.0.3".to_string(),
+            options: String::new(),
+            start_time: 1_660_000_000,
+            maintain_blocks: 600,
+        }
+    }
+
+    #[test]
+    fn test_expires_at() {
+        assert_eq!(task(1).expires_at(), Some(1_660_000_000 + 3000));
+        let one_shot = AssignedTask {
+            maintain_blocks: 0,
+            ..task(1)
+        };
+        assert_eq!(one_shot.expires_at(), None);
+    }
+
+    #[tokio::test]
+    async fn test_local_process_executor() {
+        let executor =
+            LocalProcessExecutor::new("sh", &["-c", "test \"$DEP_TASK_URL\" = \"$0\"", "{url}"]);
+        executor.start(&task(1)).await.unwrap();
+        let mut status = WorkloadStatus::Running;
+        for _ in 0..50 {
+            status = executor.poll(1).await.unwrap();
+            if status != WorkloadStatus::Running {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(status, WorkloadStatus::Succeeded);
+
+        let executor = LocalProcessExecutor::new("sleep", &["30"]);
+        executor.start(&task(2)).await.unwrap();
+        assert_eq!(executor.poll(2).await.unwrap(), WorkloadStatus::Running);
+        executor.stop(2).await.unwrap();
+        assert_eq!(executor.poll(2).await.unwrap(), WorkloadStatus::Unknown);
+
+        let flag = AssignedTask {
+            url: "--privileged".to_string(),
+            ..task(3)
+        };
+        assert!(executor.start(&flag).await.is_err());
+    }
+
+    #[test]
+    fn test_options_policy() {
+        let options = TaskOptions::decode_lenient(
+            r#"{"env": {"LD_PRELOAD": "/tmp/x.so"}, "args": ["--threads=4"]}"#,
+        );
+        assert!(OptionsPolicy::new().check(&options).is_err());
+        assert!(OptionsPolicy::new()
+            .allow_arg("--threads=*")
+            .check(&options)
+            .is_err());
+
+        let policy = OptionsPolicy::new()
+            .allow_env("LD_PRELOAD")
+            .allow_arg("--threads=*");
+        assert!(policy.check(&options).is_ok());
+        let flags = TaskOptions {
+            args: vec!["--privileged".to_string()],
+            ..TaskOptions::default()
+        };
+        assert!(policy.check(&flags).is_err());
+        assert!(OptionsPolicy::new().check(&TaskOptions::default()).is_ok());
+    }
+}
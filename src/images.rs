@@ -0,0 +1,333 @@
+//! Image persistence whitelist, rebuilt from contract events.
+//!
+//! The contract only exposes `userSetWhiteImage[address]` and
+//! `imageWhiteListStatus[url]`, so the set of whitelisted images and who set
+//! them has to be replayed from `AddImagePersistenceWhitelist` events.
+//! `addImagePersistenceWhitelist(url)` first clears the status of the
+//! caller's previous image, even when other addresses set the same image, so
+//! replacing an image can silently un-whitelist one still in use.
+//! `DeleteImage` is only a signal to nodes and leaves the status untouched.
+
+use crate::events::{DepEvent, DepLog};
+use crate::Client;
+use std::collections::{BTreeMap, HashMap};
+use web3::types::{Address, TransactionReceipt, H256};
+
+/// Where an image was last set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageUpdate {
+    pub sender: Address,
+    pub block_number: u64,
+    pub transaction_hash: H256,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageEntry {
+    pub url: String,
+    /// `imageWhiteListStatus[url]`.
+    pub whitelisted: bool,
+    /// Addresses whose `userSetWhiteImage` is this image.
+    pub set_by: Vec<Address>,
+    pub last_set: ImageUpdate,
+    /// The block of the last `DeleteImage` for this image.
+    pub delete_requested_at: Option<u64>,
+}
+
+impl ImageEntry {
+    /// Still set by someone but no longer whitelisted, because another
+    /// address that set it moved on to a different image.
+    pub fn orphaned(&self) -> bool {
+        !self.whitelisted && !self.set_by.is_empty()
+    }
+}
+
+/// The whitelist state implied by the events replayed so far.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageRegistry {
+    user_images: HashMap<Address, String>,
+    status: HashMap<String, bool>,
+    last_set: HashMap<String, ImageUpdate>,
+    deleted: HashMap<String, u64>,
+    /// The last block replayed.
+    pub to_block: u64,
+}
+
+impl ImageRegistry {
+    pub fn new() -> Self {
+        ImageRegistry::default()
+    }
+
+    /// Applies one event; events must be applied in chain order.
+    pub fn apply(&mut self, log: &DepLog) {
+        self.to_block = self.to_block.max(log.block_number);
+        match &log.event {
+            DepEvent::AddImagePersistenceWhitelist(added) => {
+                if let Some(previous) = self.user_images.get(&added.sender) {
+                    self.status.insert(previous.clone(), false);
+                }
+                self.user_images.insert(added.sender, added.url.clone());
+                self.status.insert(added.url.clone(), true);
+                self.last_set.insert(
+                    added.url.clone(),
+                    ImageUpdate {
+                        sender: added.sender,
+                        block_number: log.block_number,
+                        transaction_hash: log.transaction_hash,
+                    },
+                );
+            }
+            DepEvent::DeleteImage(deleted) => {
+                self.deleted.insert(deleted.url.clone(), log.block_number);
+            }
+            _ => {}
+        }
+    }
+
+    /// Every image ever set, whitelisted or not, sorted by url.
+    pub fn entries(&self) -> Vec<ImageEntry> {
+        let mut set_by: BTreeMap<&str, Vec<Address>> = BTreeMap::new();
+        for (user, url) in &self.user_images {
+            set_by.entry(url).or_default().push(*user);
+        }
+        let mut entries: Vec<_> = self
+            .last_set
+            .iter()
+            .map(|(url, last_set)| {
+                let mut set_by = set_by.remove(url.as_str()).unwrap_or_default();
+                set_by.sort();
+                ImageEntry {
+                    url: url.clone(),
+                    whitelisted: self.status.get(url).copied().unwrap_or(false),
+                    set_by,
+                    last_set: *last_set,
+                    delete_requested_at: self.deleted.get(url).copied(),
+                }
+            })
+            .collect();
+        entries.sort_by(|a, b| a.url.cmp(&b.url));
+        entries
+    }
+
+    /// The images whose `imageWhiteListStatus` is currently true.
+    pub fn whitelisted(&self) -> Vec<ImageEntry> {
+        self.entries()
+            .into_iter()
+            .filter(|entry| entry.whitelisted)
+            .collect()
+    }
+
+    /// `userSetWhiteImage[user]`, if the user ever set one.
+    pub fn image_of(&self, user: Address) -> Option<&str> {
+        self.user_images.get(&user).map(String::as_str)
+    }
+
+    /// What `addImagePersistenceWhitelist(url)` sent by `caller` would do.
+    pub fn preview_replace(&self, caller: Address, url: &str) -> ReplacePreview {
+        let current = self.image_of(caller).map(str::to_string);
+        let unwhitelisted = current
+            .clone()
+            .filter(|current| current != url && self.status.get(current) == Some(&true));
+        let shared_with = match &unwhitelisted {
+            Some(current) => {
+                let mut users: Vec<_> = self
+                    .user_images
+                    .iter()
+                    .filter(|(user, image)| **user != caller && *image == current)
+                    .map(|(user, _)| *user)
+                    .collect();
+                users.sort();
+                users
+            }
+            None => vec![],
+        };
+        ReplacePreview {
+            caller,
+            url: url.to_string(),
+            current,
+            unwhitelisted,
+            shared_with,
+            already_whitelisted: self.status.get(url) == Some(&true),
+        }
+    }
+}
+
+/// The effect of replacing the caller's image, computed before sending.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplacePreview {
+    pub caller: Address,
+    pub url: String,
+    /// `userSetWhiteImage[caller]` before the call.
+    pub current: Option<String>,
+    /// The image the call takes off the whitelist.
+    pub unwhitelisted: Option<String>,
+    /// Other addresses whose image is `unwhitelisted` and lose it too.
+    pub shared_with: Vec<Address>,
+    pub already_whitelisted: bool,
+}
+
+impl ReplacePreview {
+    /// Whether the call changes anything besides whitelisting `url`.
+    pub fn is_destructive(&self) -> bool {
+        self.unwhitelisted.is_some()
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        if let Some(image) = &self.unwhitelisted {
+            warnings.push(format!("{} will no longer be whitelisted", image));
+            for user in &self.shared_with {
+                warnings.push(format!("{:?} also set {} and loses it", user, image));
+            }
+        }
+        if self.already_whitelisted && self.current.as_ref() != Some(&self.url) {
+            warnings.push(format!(
+                "{} is already whitelisted by another address",
+                self.url
+            ));
+        }
+        warnings
+    }
+}
+
+impl Client {
+    /// Replays the image whitelist from `from_block`, which should be the
+    /// contract's deployment block for a complete picture, to the head.
+    pub async fn image_registry(&self, from_block: u64) -> Result<ImageRegistry, anyhow::Error> {
+        let to_block = self.block_number().await?;
+        let mut registry = ImageRegistry::new();
+        for log in self.events_between(from_block, to_block).await? {
+            registry.apply(&log);
+        }
+        registry.to_block = to_block;
+        Ok(registry)
+    }
+
+    /// Previews `caller` replacing their image with `url`; pass the
+    /// wallet's address to preview a replacement `replace_image` will send.
+    /// The caller's current image is read from the contract; who else shares
+    /// it comes from the replay.
+    pub async fn preview_replace_image(
+        &self,
+        caller: Address,
+        url: &str,
+        registry: &ImageRegistry,
+    ) -> Result<ReplacePreview, anyhow::Error> {
+        let mut registry = registry.clone();
+        let (current,) = self.user_set_white_image(caller).await?;
+        if !current.is_empty() && registry.image_of(caller) != Some(current.as_str()) {
+            // events before the replay's first block set it
+            registry.user_images.insert(caller, current.clone());
+            let (whitelisted,) = self.image_whitelist_status(&current).await?;
+            registry.status.insert(current, whitelisted);
+        }
+        let mut preview = registry.preview_replace(caller, url);
+        let (already_whitelisted,) = self.image_whitelist_status(url).await?;
+        preview.already_whitelisted = already_whitelisted;
+        Ok(preview)
+    }
+
+    /// Sends `addImagePersistenceWhitelist(preview.url)` unless the wallet's
+    /// image changed since the preview was made, so only the effects that
+    /// were reviewed can happen.
+    pub async fn replace_image(
+        &self,
+        preview: &ReplacePreview,
+    ) -> Result<TransactionReceipt, anyhow::Error> {
+        if preview.caller != self.address() {
+            anyhow::bail!("the preview is for {:?}", preview.caller);
+        }
+        let (whitelisted,) = self.address_whitelist(self.address()).await?;
+        if !whitelisted {
+            anyhow::bail!("{:?} is not on the address whitelist", self.address());
+        }
+        let (current,) = self.user_set_white_image(self.address()).await?;
+        let current = Some(current).filter(|current| !current.is_empty());
+        if current != preview.current {
+            anyhow::bail!(
+                "the wallet's image changed from {:?} to {:?} since the preview",
+                preview.current,
+                current
+            );
+        }
+        self.add_image_persistence_whitelist(&preview.url).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::{AddImagePersistenceWhitelist, DeleteImage};
+
+    fn log(block_number: u64, event: DepEvent) -> DepLog {
+        DepLog {
+            block_number,
+            transaction_hash: H256::from_low_u64_be(block_number),
+            log_index: 0,
+            event,
+        }
+    }
+
+    fn add(block_number: u64, sender: u64, url: &str) -> DepLog {
+        log(
+            block_number,
+            DepEvent::AddImagePersistenceWhitelist(AddImagePersistenceWhitelist {
+                sender: Address::from_low_u64_be(sender),
+                url: url.to_string(),
+            }),
+        )
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut registry = ImageRegistry::new();
+        for log in [
+            add(1, 1, "oracle:1.0"),
+            add(2, 2, "oracle:1.0"),
+            add(3, 3, "web:2.0"),
+            add(4, 1, "oracle:1.1"),
+            log(
+                5,
+                DepEvent::DeleteImage(DeleteImage {
+                    url: "web:2.0".to_string(),
+                }),
+            ),
+        ] {
+            registry.apply(&log);
+        }
+        let entries = registry.entries();
+        assert_eq!(
+            entries.iter().map(|e| e.url.as_str()).collect::<Vec<_>>(),
+            vec!["oracle:1.0", "oracle:1.1", "web:2.0"]
+        );
+        // address 1 moving to 1.1 un-whitelisted 1.0 under address 2
+        assert!(!entries[0].whitelisted);
+        assert!(entries[0].orphaned());
+        assert_eq!(entries[0].set_by, vec![Address::from_low_u64_be(2)]);
+        assert_eq!(entries[0].last_set.block_number, 2);
+        // DeleteImage leaves the status alone
+        assert!(entries[2].whitelisted);
+        assert_eq!(entries[2].delete_requested_at, Some(5));
+        assert_eq!(registry.whitelisted().len(), 2);
+        assert_eq!(registry.to_block, 5);
+    }
+
+    #[test]
+    fn test_preview_replace() {
+        let mut registry = ImageRegistry::new();
+        registry.apply(&add(1, 1, "oracle:1.0"));
+        registry.apply(&add(2, 2, "oracle:1.0"));
+        let preview = registry.preview_replace(Address::from_low_u64_be(1), "oracle:1.1");
+        assert!(preview.is_destructive());
+        assert_eq!(preview.unwhitelisted.as_deref(), Some("oracle:1.0"));
+        assert_eq!(preview.shared_with, vec![Address::from_low_u64_be(2)]);
+        assert_eq!(preview.warnings().len(), 2);
+
+        let same = registry.preview_replace(Address::from_low_u64_be(1), "oracle:1.0");
+        assert!(!same.is_destructive());
+        assert!(same.already_whitelisted);
+
+        let first = registry.preview_replace(Address::from_low_u64_be(9), "web:2.0");
+        assert_eq!(first.current, None);
+        assert!(first.warnings().is_empty());
+    }
+}
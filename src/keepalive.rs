@@ -0,0 +1,347 @@
+//! Keep-alive service that extends tasks with `increaseTaskDuration`.
+//!
+//! A task's workloads are maintained until `startTime + maintainBlocks * 5`.
+//! Publishers that want a task to keep running have to add blocks before
+//! then, paying `proofUnit * maxRunNum * blockPrice` EZC per extension (the
+//! same formula as publishing, applied to the extra blocks). `KeepAlive`
+//! tracks the publisher's tasks, extends each one when fewer than
+//! `lead_time` worth of blocks remain and stops spending once its budget is
+//! used up. The tracked tasks and the EZC spent are persisted, so a restart
+//! neither forgets tasks nor resets the budget.
+
+use crate::events::{DepEvent, EventWatcher};
+use crate::executor::BLOCK_TIME_SECS;
+use crate::publish::task_cost;
+use crate::state;
+use crate::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use web3::types::{H256, U256};
+
+#[derive(Debug, Clone)]
+pub struct KeepAliveConfig {
+    /// Blocks added per extension.
+    pub extend_by: u64,
+    /// Extend a task once fewer than this many seconds of blocks remain.
+    pub lead_time: Duration,
+    /// The most EZC the service may burn on extensions in total.
+    pub budget: U256,
+    pub poll_interval: Duration,
+    pub state_path: PathBuf,
+}
+
+impl Default for KeepAliveConfig {
+    fn default() -> Self {
+        KeepAliveConfig {
+            extend_by: 720,
+            lead_time: Duration::from_secs(600),
+            budget: U256::zero(),
+            poll_interval: Duration::from_secs(30),
+            state_path: PathBuf::from("keepalive-state.json"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeepAliveAlert {
+    /// The EZC balance does not cover the task's next extension.
+    InsufficientBalance {
+        task_id: u64,
+        cost: U256,
+        balance: U256,
+    },
+    /// The remaining budget does not cover the task's next extension.
+    BudgetExhausted {
+        task_id: u64,
+        cost: U256,
+        remaining: U256,
+    },
+    /// The task's maintained window ended before it was extended.
+    Lapsed {
+        task_id: u64,
+    },
+    ExtensionFailed {
+        task_id: u64,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    pub task_id: u64,
+    pub blocks: u64,
+    pub cost: U256,
+    pub transaction_hash: H256,
+}
+
+/// What one keep-alive step did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeepAliveReport {
+    pub extended: Vec<Extension>,
+    pub alerts: Vec<KeepAliveAlert>,
+}
+
+/// Blocks left in a task's maintained window at unix time `now`, counting a
+/// partly elapsed block as left.
+pub fn remaining_blocks(start_time: u64, maintain_blocks: u64, now: u64) -> u64 {
+    let end = start_time.saturating_add(maintain_blocks.saturating_mul(BLOCK_TIME_SECS));
+    let remaining_secs = end.saturating_sub(now);
+    remaining_secs.div_ceil(BLOCK_TIME_SECS)
+}
+
+/// What the keep-alive service persists between restarts.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeepAliveState {
+    pub next_block: u64,
+    pub tasks: BTreeSet<u64>,
+    /// Published tasks whose publisher is still to be checked; a task stays
+    /// here until `taskInfo` could be read.
+    pub pending: BTreeSet<u64>,
+    /// EZC burned on extensions so far, counted against the budget.
+    pub spent: U256,
+}
+
+impl KeepAliveState {
+    pub fn load(path: &Path) -> Result<Option<KeepAliveState>, anyhow::Error> {
+        state::load(path)
+    }
+
+    /// Writes the state atomically, so a crash never leaves a torn file.
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        state::save(self, path)
+    }
+}
+
+pub struct KeepAlive {
+    client: Client,
+    config: KeepAliveConfig,
+    state: KeepAliveState,
+    /// Tasks already alerted for a low balance, so the alert is not repeated
+    /// on every poll.
+    underfunded: HashSet<u64>,
+    /// Tasks already alerted for an exhausted budget, likewise.
+    unbudgeted: HashSet<u64>,
+    watcher: EventWatcher,
+}
+
+impl KeepAlive {
+    /// Loads persisted state, or starts following tasks the client's wallet
+    /// publishes from the next block on. Earlier tasks can be added with
+    /// `track`. The client needs the EZC address, without which the balance
+    /// could not be checked before extending.
+    pub async fn new(client: &Client, config: KeepAliveConfig) -> Result<Self, anyhow::Error> {
+        if client.ezc_address().is_none() {
+            anyhow::bail!("keep-alive needs the EZC address to check the balance");
+        }
+        let state = match KeepAliveState::load(&config.state_path)? {
+            Some(state) => state,
+            None => KeepAliveState {
+                next_block: client.eth.block_number().await?.as_u64() + 1,
+                ..KeepAliveState::default()
+            },
+        };
+        let watcher = EventWatcher::new(client, state.next_block);
+        Ok(KeepAlive {
+            client: client.clone(),
+            config,
+            state,
+            underfunded: HashSet::new(),
+            unbudgeted: HashSet::new(),
+            watcher,
+        })
+    }
+
+    pub fn track(&mut self, task_id: u64) {
+        self.state.tasks.insert(task_id);
+    }
+
+    pub fn untrack(&mut self, task_id: u64) {
+        self.state.tasks.remove(&task_id);
+    }
+
+    pub fn tracked(&self) -> &BTreeSet<u64> {
+        &self.state.tasks
+    }
+
+    /// EZC burned on extensions so far.
+    pub fn spent(&self) -> U256 {
+        self.state.spent
+    }
+
+    /// Polls forever, logging extensions and alerts.
+    pub async fn run(mut self) -> Result<(), anyhow::Error> {
+        loop {
+            match self.step().await {
+                Ok(report) => {
+                    for alert in report.alerts {
+                        log::warn!("keep-alive: {:?}", alert);
+                    }
+                }
+                Err(e) => log::warn!("keep-alive step failed: {}", e),
+            }
+            tokio::time::sleep(self.config.poll_interval).await;
+        }
+    }
+
+    /// Picks up newly published tasks, extends those that are due and saves
+    /// the state.
+    pub async fn step(&mut self) -> Result<KeepAliveReport, anyhow::Error> {
+        let followed = self.follow().await;
+        let extended = self.extend().await;
+        self.state.save(&self.config.state_path)?;
+        followed?;
+        extended
+    }
+
+    async fn follow(&mut self) -> Result<(), anyhow::Error> {
+        let batch = self.watcher.poll().await?;
+        for log in batch.events() {
+            if let DepEvent::TaskPublished(published) = log.event {
+                if published.maintain_blocks > 0 {
+                    self.state.pending.insert(published.task_id);
+                }
+            }
+        }
+        self.state.next_block = self.watcher.next_block();
+        for task_id in self.state.pending.clone() {
+            match self.client.calls().task_info(task_id).await {
+                Ok(info) => {
+                    self.state.pending.remove(&task_id);
+                    if info.publisher == self.client.address() {
+                        self.track(task_id);
+                    }
+                }
+                Err(e) => log::warn!("failed to read task {}, will retry: {}", task_id, e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn extend(&mut self) -> Result<KeepAliveReport, anyhow::Error> {
+        let mut report = KeepAliveReport::default();
+        let now = self.client.calls().get_curren_time().await?;
+        let mut balance = self.client.ezc_balance(self.client.address()).await?;
+        let (proof_unit,) = self.client.proof_unit().await?;
+        let (block_unit_price,) = self.client.block_unit_price().await?;
+        let lead_blocks = self.config.lead_time.as_secs() / BLOCK_TIME_SECS;
+        for task_id in self.tracked().clone() {
+            let info = match self.client.calls().task_info(task_id).await {
+                Ok(info) => info,
+                Err(e) => {
+                    log::warn!("failed to read task {}: {}", task_id, e);
+                    continue;
+                }
+            };
+            let remaining = remaining_blocks(info.start_time, info.maintain_blocks, now);
+            if remaining == 0 {
+                report.alerts.push(KeepAliveAlert::Lapsed { task_id });
+                self.untrack(task_id);
+                continue;
+            }
+            let cost = task_cost(
+                proof_unit,
+                block_unit_price,
+                info.max_run_num,
+                self.config.extend_by,
+            )
+            .ok_or_else(|| anyhow::anyhow!("blockUintPrice is zero or the cost overflows"))?;
+            if balance < cost {
+                if self.underfunded.insert(task_id) {
+                    report.alerts.push(KeepAliveAlert::InsufficientBalance {
+                        task_id,
+                        cost,
+                        balance,
+                    });
+                }
+                continue;
+            }
+            self.underfunded.remove(&task_id);
+            if remaining > lead_blocks {
+                continue;
+            }
+            let remaining_budget = self.config.budget.saturating_sub(self.state.spent);
+            if cost > remaining_budget {
+                if self.unbudgeted.insert(task_id) {
+                    report.alerts.push(KeepAliveAlert::BudgetExhausted {
+                        task_id,
+                        cost,
+                        remaining: remaining_budget,
+                    });
+                }
+                continue;
+            }
+            self.unbudgeted.remove(&task_id);
+            match self
+                .client
+                .increase_task_duration(task_id, self.config.extend_by)
+                .await
+            {
+                Ok(receipt) if receipt.status == Some(1.into()) => {
+                    log::info!(
+                        "extended task {} by {} blocks for {} EZC",
+                        task_id,
+                        self.config.extend_by,
+                        cost
+                    );
+                    self.state.spent += cost;
+                    balance = balance.saturating_sub(cost);
+                    if let Err(e) = self.state.save(&self.config.state_path) {
+                        log::error!("failed to save keep-alive state: {}", e);
+                    }
+                    report.extended.push(Extension {
+                        task_id,
+                        blocks: self.config.extend_by,
+                        cost,
+                        transaction_hash: receipt.transaction_hash,
+                    });
+                }
+                Ok(receipt) => report.alerts.push(KeepAliveAlert::ExtensionFailed {
+                    task_id,
+                    reason: format!("reverted in {:?}", receipt.transaction_hash),
+                }),
+                Err(e) => report.alerts.push(KeepAliveAlert::ExtensionFailed {
+                    task_id,
+                    reason: e.to_string(),
+                }),
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl Client {
+    /// The EZC `increaseTaskDuration(task_id, extra_blocks)` would burn at
+    /// current prices.
+    pub async fn estimate_extension_cost(
+        &self,
+        task_id: u64,
+        extra_blocks: u64,
+    ) -> Result<U256, anyhow::Error> {
+        let info = self.calls().task_info(task_id).await?;
+        let (proof_unit,) = self.proof_unit().await?;
+        let (block_unit_price,) = self.block_unit_price().await?;
+        task_cost(proof_unit, block_unit_price, info.max_run_num, extra_blocks)
+            .ok_or_else(|| anyhow::anyhow!("blockUintPrice is zero or the cost overflows"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_remaining_blocks() {
+        let start = 1_660_000_000;
+        assert_eq!(remaining_blocks(start, 600, start), 600);
+        assert_eq!(remaining_blocks(start, 600, start + 2999), 1);
+        assert_eq!(remaining_blocks(start, 600, start + 3000), 0);
+        assert_eq!(remaining_blocks(start, 600, start + 1000), 400);
+        assert_eq!(remaining_blocks(start, 600, start + 5000), 0);
+        assert_eq!(
+            remaining_blocks(start, u64::MAX, start),
+            (u64::MAX - start).div_ceil(BLOCK_TIME_SECS)
+        );
+    }
+}
@@ -0,0 +1,445 @@
+//! V3 keystore management.
+//!
+//! `KeyStore` keeps encrypted wallets in one directory, one file per key,
+//! named after the key. Files carry the geth `address` field so keys can be
+//! listed without their passwords. Passwords, and the secrets being
+//! imported, come from a `SecretSource` rather than from literals.
+
+use ethers::prelude::k256::ecdsa::SigningKey;
+use ethers::signers::coins_bip39::English;
+use ethers::signers::{LocalWallet, MnemonicBuilder, Signer};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use web3::types::Address;
+
+/// Where a password or other secret is read from.
+///
+/// Parsed from `prompt`, `env:VAR`, `file:PATH` or `keyring:NAME`. There is
+/// deliberately no variant holding the secret itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretSource {
+    Prompt,
+    Env(String),
+    /// The first line of a file.
+    File(PathBuf),
+    /// An entry in the default `Keyring`.
+    Keyring(String),
+}
+
+impl SecretSource {
+    /// Reads the secret, prompting with `prompt` if needed.
+    pub fn read(&self, prompt: &str) -> Result<String, anyhow::Error> {
+        match self {
+            SecretSource::Prompt => Ok(rpassword::prompt_password(prompt)?),
+            SecretSource::Env(var) => std::env::var(var)
+                .map_err(|_| anyhow::anyhow!("environment variable {} is not set", var)),
+            SecretSource::File(path) => {
+                let contents = fs::read_to_string(path)?;
+                Ok(contents.lines().next().unwrap_or_default().to_string())
+            }
+            SecretSource::Keyring(name) => Keyring::open_default()?
+                .get(name)?
+                .ok_or_else(|| anyhow::anyhow!("no keyring entry {}", name)),
+        }
+    }
+
+    /// Reads a secret that is about to be set, asking twice when prompting.
+    pub fn read_new(&self, prompt: &str) -> Result<String, anyhow::Error> {
+        let secret = self.read(prompt)?;
+        if *self == SecretSource::Prompt && secret != self.read("Repeat: ")? {
+            anyhow::bail!("the passwords do not match");
+        }
+        if secret.is_empty() {
+            anyhow::bail!("empty password");
+        }
+        Ok(secret)
+    }
+}
+
+impl FromStr for SecretSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "prompt" {
+            return Ok(SecretSource::Prompt);
+        }
+        match s.split_once(':') {
+            Some(("env", var)) if !var.is_empty() => Ok(SecretSource::Env(var.to_string())),
+            Some(("file", path)) if !path.is_empty() => Ok(SecretSource::File(path.into())),
+            Some(("keyring", name)) if !name.is_empty() => {
+                Ok(SecretSource::Keyring(name.to_string()))
+            }
+            _ => anyhow::bail!(
+                "expected prompt, env:VAR, file:PATH or keyring:NAME, secrets are not accepted inline"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for SecretSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretSource::Prompt => write!(f, "prompt"),
+            SecretSource::Env(var) => write!(f, "env:{}", var),
+            SecretSource::File(path) => write!(f, "file:{}", path.display()),
+            SecretSource::Keyring(name) => write!(f, "keyring:{}", name),
+        }
+    }
+}
+
+/// A stand-in for the OS keyring: one owner-only file per entry under
+/// `$DEP_KEYRING_DIR`, or `~/.config/dep/keyring`.
+pub struct Keyring {
+    dir: PathBuf,
+}
+
+impl Keyring {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        restrict(&dir, 0o700)?;
+        Ok(Keyring { dir })
+    }
+
+    pub fn open_default() -> Result<Self, anyhow::Error> {
+        match std::env::var_os("DEP_KEYRING_DIR") {
+            Some(dir) => Keyring::open(dir),
+            None => {
+                let home = std::env::var_os("HOME")
+                    .ok_or_else(|| anyhow::anyhow!("neither DEP_KEYRING_DIR nor HOME is set"))?;
+                Keyring::open(Path::new(&home).join(".config/dep/keyring"))
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<String>, anyhow::Error> {
+        match fs::read_to_string(self.dir.join(checked_name(name)?)) {
+            Ok(secret) => Ok(Some(secret)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn set(&self, name: &str, secret: &str) -> Result<(), anyhow::Error> {
+        let path = self.dir.join(checked_name(name)?);
+        let tmp = self.dir.join(format!(".{}.tmp", name));
+        fs::write(&tmp, "")?;
+        restrict(&tmp, 0o600)?;
+        fs::write(&tmp, secret)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Returns whether the entry existed.
+    pub fn delete(&self, name: &str) -> Result<bool, anyhow::Error> {
+        match fs::remove_file(self.dir.join(checked_name(name)?)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn restrict(path: &Path, mode: u32) -> Result<(), anyhow::Error> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict(_path: &Path, _mode: u32) -> Result<(), anyhow::Error> {
+    Ok(())
+}
+
+/// Names become file names, so they are limited to `[A-Za-z0-9._-]` and may
+/// not start with a dot.
+fn checked_name(name: &str) -> Result<&str, anyhow::Error> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if !valid {
+        anyhow::bail!("invalid key name {:?}", name);
+    }
+    Ok(name)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyInfo {
+    pub name: String,
+    /// `None` for files without the `address` field, such as those written by
+    /// `LocalWallet::new_keystore`.
+    pub address: Option<Address>,
+    pub path: PathBuf,
+}
+
+pub struct KeyStore {
+    dir: PathBuf,
+}
+
+impl KeyStore {
+    /// Opens a keystore directory, creating it if needed.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(KeyStore { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn path(&self, name: &str) -> Result<PathBuf, anyhow::Error> {
+        Ok(self.dir.join(checked_name(name)?))
+    }
+
+    /// Generates a new key. `name` defaults to the key's address.
+    pub fn create(&self, name: Option<&str>, password: &str) -> Result<KeyInfo, anyhow::Error> {
+        self.store(name, &LocalWallet::new(&mut rand::thread_rng()), password)
+    }
+
+    /// Imports a hex private key, with or without `0x`.
+    pub fn import_private_key(
+        &self,
+        name: Option<&str>,
+        private_key: &str,
+        password: &str,
+    ) -> Result<KeyInfo, anyhow::Error> {
+        let private_key = private_key.trim();
+        let wallet: LocalWallet = private_key
+            .strip_prefix("0x")
+            .unwrap_or(private_key)
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid private key"))?;
+        self.store(name, &wallet, password)
+    }
+
+    /// Imports the key at `m/44'/60'/0'/0/{index}` of a BIP-39 phrase.
+    pub fn import_mnemonic(
+        &self,
+        name: Option<&str>,
+        phrase: &str,
+        index: u32,
+        password: &str,
+    ) -> Result<KeyInfo, anyhow::Error> {
+        let wallet = MnemonicBuilder::<English>::default()
+            .phrase(phrase.trim())
+            .index(index)?
+            .build()?;
+        self.store(name, &wallet, password)
+    }
+
+    /// Every key in the directory, sorted by name.
+    pub fn list(&self) -> Result<Vec<KeyInfo>, anyhow::Error> {
+        let mut keys = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !entry.file_type()?.is_file() || checked_name(&name).is_err() {
+                continue;
+            }
+            match stored_address(&entry.path()) {
+                Ok(address) => keys.push(KeyInfo {
+                    name,
+                    address,
+                    path: entry.path(),
+                }),
+                Err(e) => log::warn!("skipping {}: {}", entry.path().display(), e),
+            }
+        }
+        keys.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(keys)
+    }
+
+    /// The key's address, decrypting the key only when the file does not
+    /// record it.
+    pub fn address(
+        &self,
+        name: &str,
+        password: impl FnOnce() -> Result<String, anyhow::Error>,
+    ) -> Result<Address, anyhow::Error> {
+        match stored_address(&self.path(name)?)? {
+            Some(address) => Ok(address),
+            None => Ok(self.unlock(name, &password()?)?.address()),
+        }
+    }
+
+    pub fn unlock(&self, name: &str, password: &str) -> Result<LocalWallet, anyhow::Error> {
+        LocalWallet::decrypt_keystore(self.path(name)?, password)
+            .map_err(|e| anyhow::anyhow!("cannot unlock {}: {}", name, e))
+    }
+
+    /// Re-encrypts a key, replacing the file only once the new one is
+    /// written.
+    pub fn change_password(
+        &self,
+        name: &str,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<KeyInfo, anyhow::Error> {
+        let wallet = self.unlock(name, old_password)?;
+        self.write(name, &wallet, new_password, true)
+    }
+
+    fn store(
+        &self,
+        name: Option<&str>,
+        wallet: &LocalWallet,
+        password: &str,
+    ) -> Result<KeyInfo, anyhow::Error> {
+        let default_name = format!("{:?}", wallet.address());
+        self.write(name.unwrap_or(&default_name), wallet, password, false)
+    }
+
+    fn write(
+        &self,
+        name: &str,
+        wallet: &LocalWallet,
+        password: &str,
+        replace: bool,
+    ) -> Result<KeyInfo, anyhow::Error> {
+        let path = self.path(name)?;
+        if !replace && path.exists() {
+            anyhow::bail!("{} already exists", path.display());
+        }
+        let secret: &SigningKey = wallet.signer();
+        let uuid = eth_keystore::encrypt_key(
+            &self.dir,
+            &mut rand::thread_rng(),
+            secret.to_bytes(),
+            password,
+        )?;
+        // eth-keystore names the file after its uuid and omits the address
+        let tmp = self.dir.join(uuid);
+        let mut keystore: serde_json::Value = serde_json::from_str(&fs::read_to_string(&tmp)?)?;
+        keystore["address"] = format!("{:x}", wallet.address()).into();
+        fs::write(&tmp, keystore.to_string())?;
+        restrict(&tmp, 0o600)?;
+        fs::rename(&tmp, &path)?;
+        Ok(KeyInfo {
+            name: name.to_string(),
+            address: Some(wallet.address()),
+            path,
+        })
+    }
+}
+
+/// The address a V3 keystore file records, read without decrypting it.
+pub fn keystore_address(path: &Path) -> Result<Address, anyhow::Error> {
+    stored_address(path)?
+        .ok_or_else(|| anyhow::anyhow!("{} does not record its address", path.display()))
+}
+
+fn stored_address(path: &Path) -> Result<Option<Address>, anyhow::Error> {
+    let keystore: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    if keystore["version"] != 3 {
+        anyhow::bail!("not a V3 keystore");
+    }
+    match keystore["address"].as_str() {
+        Some(address) => Ok(Some(address.parse()?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dep-keys-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_secret_sources() {
+        assert_eq!(
+            "prompt".parse::<SecretSource>().unwrap(),
+            SecretSource::Prompt
+        );
+        assert_eq!(
+            "env:DEP_PASSWORD".parse::<SecretSource>().unwrap(),
+            SecretSource::Env("DEP_PASSWORD".into())
+        );
+        assert_eq!(
+            "file:/run/secrets/dep".parse::<SecretSource>().unwrap(),
+            SecretSource::File("/run/secrets/dep".into())
+        );
+        assert_eq!(
+            "keyring:operator".parse::<SecretSource>().unwrap(),
+            SecretSource::Keyring("operator".into())
+        );
+        assert!("not-a-source".parse::<SecretSource>().is_err());
+        assert!("env:".parse::<SecretSource>().is_err());
+
+        let dir = temp_dir("sources");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("password"), "secret\n").unwrap();
+        let source = SecretSource::File(dir.join("password"));
+        assert_eq!(source.read("").unwrap(), "secret");
+        let keyring = Keyring::open(dir.join("keyring")).unwrap();
+        keyring.set("operator", "from-keyring").unwrap();
+        assert_eq!(keyring.get("operator").unwrap().unwrap(), "from-keyring");
+        assert!(keyring.delete("operator").unwrap());
+        assert_eq!(keyring.get("operator").unwrap(), None);
+        assert!(keyring.set("../escape", "x").is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_keystore() {
+        let dir = temp_dir("store");
+        let store = KeyStore::open(&dir).unwrap();
+        let imported = store
+            .import_private_key(
+                Some("operator"),
+                "0x0000000000000000000000000000000000000000000000000000000000000001",
+                "first",
+            )
+            .unwrap();
+        let expected: Address = "7e5f4552091a69125d5dfcb7b8c2659029395bdf".parse().unwrap();
+        assert_eq!(imported.address, Some(expected));
+        assert!(store
+            .import_private_key(Some("operator"), "01", "first")
+            .is_err());
+
+        let mnemonic = store
+            .import_mnemonic(
+                None,
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+                0,
+                "second",
+            )
+            .unwrap();
+        let expected: Address = "9858effd232b4033e47d90003d41ec34ecaeda94".parse().unwrap();
+        assert_eq!(mnemonic.address, Some(expected));
+        assert_eq!(mnemonic.name, format!("{:?}", expected));
+
+        let names: Vec<_> = store
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|key| key.name)
+            .collect();
+        assert_eq!(names, vec![mnemonic.name.clone(), "operator".to_string()]);
+
+        store.change_password("operator", "first", "third").unwrap();
+        assert!(store.unlock("operator", "first").is_err());
+        assert_eq!(
+            store.unlock("operator", "third").unwrap().address(),
+            imported.address.unwrap()
+        );
+        assert_eq!(
+            store
+                .address("operator", || anyhow::bail!("not needed"))
+                .unwrap(),
+            imported.address.unwrap()
+        );
+        assert_eq!(store.list().unwrap().len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
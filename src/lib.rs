@@ -1,505 +1,425 @@
+pub mod abi;
+pub mod account;
+pub mod bindings;
+pub mod cache;
+pub mod control;
+pub mod credit;
+pub mod day;
+pub mod events;
+pub mod executor;
+pub mod images;
+pub mod keepalive;
+pub mod keys;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod network;
+pub mod options;
+pub mod publish;
+pub mod reconcile;
+pub mod refund;
+pub mod revert;
+pub mod rewards;
+pub mod rollout;
+pub mod runner;
+pub mod state;
+pub mod status;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod transport;
+pub mod whitelist;
+
+use abi::AbiVersion;
 use anyhow::Result;
+use cache::Cache;
+use day::DepDay;
 use ethers::prelude::k256::ecdsa::SigningKey;
 use ethers::prelude::{Wallet, U256};
 use ethers::signers::Signer;
 use secp256k1::SecretKey;
 use std::default::Default;
 use std::fs::OpenOptions;
+use std::future::Future;
 use std::io::Read;
 use std::str::FromStr;
-use web3::api::Eth;
-use web3::contract::tokens::Tokenize;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{field, Instrument};
+use transport::{MeteredHttp, RpcStats};
+use web3::api::{Eth, Namespace};
+use web3::contract::tokens::{Detokenize, Tokenize};
 use web3::contract::{Contract, Options};
-use web3::transports::Http;
-use web3::types::{Address, TransactionReceipt};
+use web3::ethabi::Token;
+use web3::types::{Address, BlockId, BlockNumber, Bytes, CallRequest, TransactionReceipt};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Client {
-    eth: Eth<Http>,
-    contract: Contract<Http>,
+    eth: Eth<MeteredHttp>,
+    contract: Contract<MeteredHttp>,
     wallet: Wallet<SigningKey>,
+    block: Option<BlockId>,
+    cache: Option<Arc<Cache>>,
+    abi_version: Option<AbiVersion>,
+    ezc_address: Option<Address>,
 }
 
 impl Client {
+    /// Builds a client from an ABI JSON file on disk.
     pub fn new(
         chain: &str,
         contract_addr: &str,
         abi_path: &str,
         wallet: Wallet<SigningKey>,
     ) -> Result<Self, anyhow::Error> {
-        let transport = Http::new(chain)?;
-        let web3 = web3::Web3::new(transport);
-        let eth = web3.eth();
         let mut abi = vec![];
         OpenOptions::new()
             .read(true)
             .open(abi_path)?
             .read_to_end(&mut abi)?;
-        let contract = Contract::from_json(eth.clone(), Address::from_str(contract_addr)?, &abi)?;
+        Client::from_abi(chain, contract_addr, &abi, wallet)
+    }
+
+    /// Builds a client from ABI JSON held in memory.
+    pub fn from_abi(
+        chain: &str,
+        contract_addr: &str,
+        abi: &[u8],
+        wallet: Wallet<SigningKey>,
+    ) -> Result<Self, anyhow::Error> {
+        let transport = MeteredHttp::new(chain)?;
+        let web3 = web3::Web3::new(transport);
+        let eth = web3.eth();
+        let contract = Contract::from_json(eth.clone(), Address::from_str(contract_addr)?, abi)?;
         Ok(Client {
             eth,
             contract,
             wallet,
+            block: None,
+            cache: None,
+            abi_version: None,
+            ezc_address: None,
         })
     }
 
-    pub async fn read_u64(&self, func: &str) -> Result<(u64,), anyhow::Error> {
-        let result: (u64,) = self
-            .contract
-            .query(
-                func,
-                (),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
+    /// Builds a client from one of the ABI versions embedded in the crate.
+    pub fn with_abi_version(
+        chain: &str,
+        contract_addr: &str,
+        version: AbiVersion,
+        wallet: Wallet<SigningKey>,
+    ) -> Result<Self, anyhow::Error> {
+        let client = Client::from_abi(chain, contract_addr, version.json(), wallet)?;
+        Ok(Client {
+            abi_version: Some(version),
+            ..client
+        })
+    }
+
+    /// The embedded ABI version this client was built from, if any.
+    pub fn abi_version(&self) -> Option<AbiVersion> {
+        self.abi_version
+    }
+
+    /// Checks that the deployed contract reports the expected
+    /// `implementationVersion()`.
+    pub async fn verify_abi_version(&self, version: AbiVersion) -> Result<(), anyhow::Error> {
+        let (deployed,) = self.implementation_version().await?;
+        if deployed != version.as_str() {
+            anyhow::bail!(
+                "contract {:?} runs DEP {} but the ABI is for {}",
+                self.contract.address(),
+                deployed,
+                version
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the embedded ABI version matching the deployed contract.
+    pub async fn detect_abi_version(&self) -> Result<AbiVersion, anyhow::Error> {
+        let (deployed,) = self.implementation_version().await?;
+        deployed.parse()
+    }
+
+    /// Returns a client that knows where the EZC token is deployed. The DEP
+    /// contract keeps its EZC address private, so it cannot be read on chain.
+    pub fn with_ezc_address(&self, ezc_address: Address) -> Client {
+        Client {
+            ezc_address: Some(ezc_address),
+            ..self.clone()
+        }
+    }
+
+    pub fn ezc_address(&self) -> Option<Address> {
+        self.ezc_address
+    }
+
+    /// Returns a view of this client whose reads are all pinned to `block`.
+    ///
+    /// Writes are unaffected and always go against the latest state.
+    pub fn at_block(&self, block: BlockId) -> Client {
+        Client {
+            block: Some(block),
+            ..self.clone()
+        }
+    }
+
+    /// Pins a view of this client to the current head block, so that several
+    /// reads observe the same chain state.
+    pub async fn snapshot(&self) -> Result<Client, anyhow::Error> {
+        let head = self.eth.block_number().await?;
+        Ok(self.at_block(BlockId::Number(BlockNumber::Number(head))))
+    }
+
+    /// The address of the wallet that signs this client's transactions.
+    pub fn address(&self) -> Address {
+        self.wallet.address()
+    }
+
+    /// Request, error and latency counters of every RPC this client and its
+    /// clones have made.
+    pub fn rpc_stats(&self) -> Arc<RpcStats> {
+        self.eth.transport().stats()
+    }
+
+    /// The block reads are pinned to, or `None` when reading the latest state.
+    pub fn block(&self) -> Option<BlockId> {
+        self.block
+    }
+
+    async fn query<R: Detokenize>(
+        &self,
+        func: &str,
+        params: impl Tokenize,
+    ) -> Result<R, anyhow::Error> {
+        let params = params.into_tokens();
+        let cache = self.cache.as_ref().filter(|_| self.block.is_none());
+        if let Some(tokens) = cache.and_then(|cache| cache.get(func, &params)) {
+            tracing::trace!(function = func, args = ?params, "cache hit");
+            return Ok(R::from_tokens(tokens)?);
+        }
+        let tokens = self.query_tokens(func, &params).await?;
+        if let Some(cache) = cache {
+            cache.insert(func, &params, tokens.clone());
+        }
+        Ok(R::from_tokens(tokens)?)
+    }
+
+    async fn query_tokens(
+        &self,
+        func: &str,
+        params: &[Token],
+    ) -> Result<Vec<Token>, anyhow::Error> {
+        self.traced_call(func, params, self.call_function(func, params))
+            .await
+    }
+
+    /// Runs a read in a `call` span and records why it reverted, if it did.
+    pub(crate) async fn traced_call<T>(
+        &self,
+        func: &str,
+        params: &[Token],
+        call: impl Future<Output = Result<T, anyhow::Error>>,
+    ) -> Result<T, anyhow::Error> {
+        let span = tracing::debug_span!(
+            "call",
+            function = func,
+            args = ?params,
+            block = ?self.block,
+            revert_reason = field::Empty,
+        );
+        let result = call.instrument(span.clone()).await;
+        if let Some(reason) = result.as_ref().err().and_then(revert::revert_reason) {
+            span.record("revert_reason", reason.as_str());
+            tracing::debug!(parent: &span, function = func, %reason, "call reverted");
+        }
+        result
+    }
+
+    async fn call_function(
+        &self,
+        func: &str,
+        params: &[Token],
+    ) -> Result<Vec<Token>, anyhow::Error> {
+        let function = self.contract.abi().function(func)?;
+        let result = self
+            .eth
+            .call(
+                CallRequest {
+                    from: Some(self.wallet.address()),
+                    to: Some(self.contract.address()),
+                    data: Some(Bytes(function.encode_input(params)?)),
+                    ..CallRequest::default()
                 },
-                None,
+                self.block,
             )
             .await?;
-        Ok(result)
+        Ok(function.decode_output(&result.0)?)
+    }
+
+    pub async fn read_u64(&self, func: &str) -> Result<(u64,), anyhow::Error> {
+        self.query(func, ()).await
     }
 
     pub async fn task_info(
         &self,
         task_id: u64,
     ) -> Result<(U256, U256, U256, U256, U256, U256, U256, Address), anyhow::Error> {
-        let result: (U256, U256, U256, U256, U256, U256, U256, Address) = self
-            .contract
-            .query(
-                "taskInfo",
-                (U256::from(task_id),),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        let params = (U256::from(task_id),).into_tokens();
+        let cache = match self.cache.as_ref().filter(|_| self.block.is_none()) {
+            Some(cache) => cache,
+            None => return self.query("taskInfo", params.as_slice()).await,
+        };
+        if let Some(tokens) = cache.get("taskInfo", &params) {
+            return Ok(Detokenize::from_tokens(tokens)?);
+        }
+        let tokens = self.query_tokens("taskInfo", &params).await?;
+        let info: (U256, U256, U256, U256, U256, U256, U256, Address) =
+            Detokenize::from_tokens(tokens.clone())?;
+        if !cache.is_invalidated() {
+            cache.insert("taskInfo", &params, tokens);
+            return Ok(info);
+        }
+        // Once past completeTimeout a task can only change through
+        // increaseTaskDuration, and the invalidator drops the entry when the
+        // matching AddTaskDuration event shows up.
+        let complete_timeout = self.calls().complete_timeout().await?;
+        let now = self.calls().get_curren_time().await?;
+        if !info.2.is_zero() && info.2 + complete_timeout < U256::from(now) {
+            cache.insert_permanent("taskInfo", &params, tokens);
+        }
+        Ok(info)
     }
 
     pub async fn address_whitelist(&self, address: Address) -> Result<(bool,), anyhow::Error> {
-        let result: (bool,) = self
-            .contract
-            .query(
-                "addressWhitelist",
-                (address,),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().address_whitelist(address).await?,))
     }
 
     pub async fn block_unit_price(&self) -> Result<(u64,), anyhow::Error> {
-        let result: (u64,) = self
-            .contract
-            .query(
-                "blockUintPrice",
-                (),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().block_uint_price().await?,))
     }
 
     pub async fn complete_timeout(&self) -> Result<(u64,), anyhow::Error> {
-        Ok(self.read_u64("completeTimeout").await?)
+        Ok((self.calls().complete_timeout().await?,))
     }
 
     pub async fn credit_threshold(&self) -> Result<(u64,), anyhow::Error> {
-        Ok(self.read_u64("creditThreshold").await?)
+        Ok((self.calls().credit_threshold().await?,))
     }
 
-    pub async fn day_total_reward(&self, day: u64) -> Result<(u64,), anyhow::Error> {
-        let result: (u64,) = self
-            .contract
-            .query(
-                "dayTotalReward",
-                (U256::from(day),),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+    pub async fn day_total_reward(&self, day: impl Into<DepDay>) -> Result<(U256,), anyhow::Error> {
+        Ok((self.calls().day_total_reward(day.into().0).await?,))
     }
 
     pub async fn estimate_run_num(&self) -> Result<(u64,), anyhow::Error> {
-        Ok(self.read_u64("estimateRunNum").await?)
+        Ok((self.calls().estimate_run_num().await?,))
     }
 
     pub async fn get_current_time(&self) -> Result<(u64,), anyhow::Error> {
-        Ok(self.read_u64("getCurrenTime").await?)
+        Ok((self.calls().get_curren_time().await?,))
     }
 
     pub async fn get_current_day(&self) -> Result<(u64,), anyhow::Error> {
-        Ok(self.read_u64("getCurrentDay").await?)
+        Ok((self.calls().get_current_day().await?,))
     }
 
     pub async fn get_sub_index_for_task(&self, task_id: u64) -> Result<(bool,), anyhow::Error> {
-        let result: (bool,) = self
-            .contract
-            .query(
-                "getSubIndexForTask",
-                (U256::from(task_id),),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().get_sub_index_for_task(task_id).await?,))
     }
 
     pub async fn get_task_remaining_time(&self, task_id: u64) -> Result<(u64,), anyhow::Error> {
-        let result: (u64,) = self
-            .contract
-            .query(
-                "getTaskRemainingTime",
-                (U256::from(task_id),),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().get_task_remaining_time(task_id).await?,))
     }
 
-    pub async fn get_total_reward_for_day(&self, the_day: u64) -> Result<(U256,), anyhow::Error> {
-        let result: (U256,) = self
-            .contract
-            .query(
-                "getTotalRewardForDay",
-                (U256::from(the_day),),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+    pub async fn get_total_reward_for_day(
+        &self,
+        the_day: impl Into<DepDay>,
+    ) -> Result<(U256,), anyhow::Error> {
+        Ok((self
+            .calls()
+            .get_total_reward_for_day(the_day.into().0)
+            .await?,))
     }
 
     pub async fn get_user_reward_for_current_day(
         &self,
         user: Address,
     ) -> Result<(U256,), anyhow::Error> {
-        let result: (U256,) = self
-            .contract
-            .query(
-                "getUserRewardForCurrentDay",
-                (user,),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().get_user_reward_for_current_day(user).await?,))
     }
 
     pub async fn get_user_reward_for_day(
         &self,
         user: Address,
-        the_day: u64,
+        the_day: impl Into<DepDay>,
     ) -> Result<(U256,), anyhow::Error> {
-        let result: (U256,) = self
-            .contract
-            .query(
-                "getUserRewardForDay",
-                (user, U256::from(the_day)),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self
+            .calls()
+            .get_user_reward_for_day(user, the_day.into().0)
+            .await?,))
     }
 
     pub async fn get_user_reward_pointer(&self, user: Address) -> Result<(u64,), anyhow::Error> {
-        let result: (u64,) = self
-            .contract
-            .query(
-                "getUserRewardPointer",
-                (user,),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().get_user_reward_pointer(user).await?,))
     }
 
     pub async fn image_whitelist_status(&self, image: &str) -> Result<(bool,), anyhow::Error> {
-        let result: (bool,) = self
-            .contract
-            .query(
-                "imageWhiteListStatus",
-                (image.to_string(),),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().image_white_list_status(image).await?,))
     }
 
     pub async fn implementation_version(&self) -> Result<(String,), anyhow::Error> {
-        let result: (String,) = self
-            .contract
-            .query(
-                "implementationVersion",
-                (),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().implementation_version().await?,))
     }
 
     pub async fn init_run_num(&self) -> Result<(u64,), anyhow::Error> {
-        let result: (u64,) = self
-            .contract
-            .query(
-                "initRunNum",
-                (),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().init_run_num().await?,))
     }
 
     pub async fn is_withdraw_from_owner(&self, task_id: u64) -> Result<(bool,), anyhow::Error> {
-        let result: (bool,) = self
-            .contract
-            .query(
-                "isWithdrawFromOwner",
-                (U256::from(task_id),),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().is_withdraw_from_owner(task_id).await?,))
     }
 
     pub async fn owner(&self) -> Result<(Address,), anyhow::Error> {
-        let result: (Address,) = self
-            .contract
-            .query(
-                "owner",
-                (),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().owner().await?,))
     }
 
     pub async fn proof_unit(&self) -> Result<(U256,), anyhow::Error> {
-        let result: (U256,) = self
-            .contract
-            .query(
-                "proofUnit",
-                (),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().proof_unit().await?,))
     }
 
-    pub async fn race_timeout(&self) -> Result<(U256,), anyhow::Error> {
-        let result: (U256,) = self
-            .contract
-            .query(
-                "raceTimeout",
-                (),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+    pub async fn race_timeout(&self) -> Result<(u64,), anyhow::Error> {
+        Ok((self.calls().race_timeout().await?,))
     }
 
-    pub async fn start_day(&self) -> Result<(U256,), anyhow::Error> {
-        let result: (U256,) = self
-            .contract
-            .query(
-                "startDay",
-                (),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+    pub async fn start_day(&self) -> Result<(u64,), anyhow::Error> {
+        Ok((self.calls().start_day().await?,))
     }
 
     pub async fn task_sum(&self) -> Result<(u64,), anyhow::Error> {
-        let result: (u64,) = self
-            .contract
-            .query(
-                "taskSum",
-                (),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().task_sum().await?,))
     }
 
     pub async fn user_day_reward(
         &self,
         address: Address,
-        day: u64,
+        day: impl Into<DepDay>,
     ) -> Result<(U256,), anyhow::Error> {
-        let result: (U256,) = self
-            .contract
-            .query(
-                "userDayReward",
-                (address, day),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().user_day_reward(address, day.into().0).await?,))
     }
 
     pub async fn user_reward_point(&self, user: Address) -> Result<(u64,), anyhow::Error> {
-        let result: (u64,) = self
-            .contract
-            .query(
-                "userRewardPoint",
-                (user,),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().user_reward_point(user).await?,))
     }
 
     pub async fn user_set_white_image(&self, user: Address) -> Result<(String,), anyhow::Error> {
-        let result: (String,) = self
-            .contract
-            .query(
-                "userSetWhiteImage",
-                (user,),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().user_set_white_image(user).await?,))
     }
 
     pub async fn user_settled_day(&self, user: Address) -> Result<(u64,), anyhow::Error> {
-        let result: (u64,) = self
-            .contract
-            .query(
-                "userSettledDay",
-                (user,),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().user_settled_day(user).await?,))
     }
 
     pub async fn user_task(&self, user: Address, task_id: u64) -> Result<(bool,), anyhow::Error> {
-        let result: (bool,) = self
-            .contract
-            .query(
-                "userTask",
-                (user, task_id),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().user_task(user, task_id).await?,))
     }
 
     pub async fn user_task_completed(
@@ -507,20 +427,7 @@ impl Client {
         user: Address,
         task_id: u64,
     ) -> Result<(bool,), anyhow::Error> {
-        let result: (bool,) = self
-            .contract
-            .query(
-                "userTask",
-                (user, task_id),
-                self.wallet.address(),
-                Options {
-                    gas: Some(140850_u64.into()),
-                    ..Options::default()
-                },
-                None,
-            )
-            .await?;
-        Ok(result)
+        Ok((self.calls().user_task_completed(user, task_id).await?,))
     }
 
     async fn write_contract(
@@ -528,10 +435,45 @@ impl Client {
         func: &str,
         params: impl Tokenize + Clone,
     ) -> Result<TransactionReceipt, anyhow::Error> {
+        let args = params.clone().into_tokens();
+        let span = tracing::info_span!(
+            "send",
+            function = func,
+            args = ?args,
+            from = ?self.wallet.address(),
+            nonce = field::Empty,
+            gas = field::Empty,
+            tx_hash = field::Empty,
+            block = field::Empty,
+            confirmation_ms = field::Empty,
+            status = field::Empty,
+            revert_reason = field::Empty,
+        );
+        let result = self
+            .send_transaction(func, params, &args)
+            .instrument(span.clone())
+            .await;
+        if let Err(e) = &result {
+            if let Some(reason) = revert::revert_reason(e) {
+                span.record("revert_reason", reason.as_str());
+            }
+            tracing::warn!(parent: &span, function = func, error = %e, "transaction failed");
+        }
+        result
+    }
+
+    async fn send_transaction(
+        &self,
+        func: &str,
+        params: impl Tokenize + Clone,
+        args: &[Token],
+    ) -> Result<TransactionReceipt, anyhow::Error> {
+        let span = tracing::Span::current();
         let nonce = self
             .eth
             .transaction_count(self.wallet.address(), None)
             .await?;
+        span.record("nonce", nonce.low_u64());
         let gas = self
             .contract
             .estimate_gas(
@@ -544,7 +486,9 @@ impl Client {
                 },
             )
             .await?;
-        let result = self
+        span.record("gas", gas.low_u64());
+        let start = Instant::now();
+        let receipt = self
             .contract
             .signed_call_with_confirmations(
                 func,
@@ -558,34 +502,61 @@ impl Client {
                 &SecretKey::from_slice(&self.wallet.signer().to_bytes())?,
             )
             .await?;
-        Ok(result)
+        let confirmation_ms = start.elapsed().as_millis() as u64;
+        let block = receipt.block_number.unwrap_or_default().as_u64();
+        let succeeded = receipt.status == Some(1.into());
+        span.record("tx_hash", field::debug(receipt.transaction_hash));
+        span.record("block", block);
+        span.record("confirmation_ms", confirmation_ms);
+        span.record("status", succeeded);
+        if succeeded {
+            tracing::info!(
+                function = func,
+                tx_hash = ?receipt.transaction_hash,
+                block,
+                gas_used = ?receipt.gas_used,
+                confirmation_ms,
+                "transaction mined"
+            );
+        } else {
+            let data = self.contract.abi().function(func)?.encode_input(args)?;
+            let reason = self
+                .replay_revert_reason(self.wallet.address(), data, Some(gas), block)
+                .await;
+            if let Some(reason) = &reason {
+                span.record("revert_reason", reason.as_str());
+            }
+            tracing::warn!(
+                function = func,
+                tx_hash = ?receipt.transaction_hash,
+                block,
+                confirmation_ms,
+                reason = reason.as_deref().unwrap_or("unknown"),
+                "transaction reverted"
+            );
+        }
+        Ok(receipt)
     }
 
     pub async fn add_image_persistence_whitelist(
         &self,
         url: &str,
     ) -> Result<TransactionReceipt, anyhow::Error> {
-        Ok(self
-            .write_contract("addImagePersistenceWhitelist", (url.to_string(),))
-            .await?)
+        self.calls().add_image_persistence_whitelist(url).await
     }
 
     pub async fn complete_sub_index_for_task(
         &self,
         task_id: u64,
     ) -> Result<TransactionReceipt, anyhow::Error> {
-        Ok(self
-            .write_contract("completeSubIndexForTask", (task_id,))
-            .await?)
+        self.calls().complete_sub_index_for_task(task_id).await
     }
 
     pub async fn delete_image(
         &self,
         image_hash: &str,
     ) -> Result<TransactionReceipt, anyhow::Error> {
-        Ok(self
-            .write_contract("deleteImage", (image_hash.to_string(),))
-            .await?)
+        self.calls().delete_image(image_hash).await
     }
 
     pub async fn increase_task_duration(
@@ -593,9 +564,9 @@ impl Client {
         task_id: u64,
         maintain_extra_blocks: u64,
     ) -> Result<TransactionReceipt, anyhow::Error> {
-        Ok(self
-            .write_contract("increaseTaskDuration", (task_id, maintain_extra_blocks))
-            .await?)
+        self.calls()
+            .increase_task_duration(task_id, maintain_extra_blocks)
+            .await
     }
 
     pub async fn n_node_unspecified_address_task(
@@ -605,17 +576,9 @@ impl Client {
         max_run_num: u64,
         maintain_blocks: u64,
     ) -> Result<TransactionReceipt, anyhow::Error> {
-        Ok(self
-            .write_contract(
-                "nNodeUnSpecifiedAddressTask",
-                (
-                    url.to_string(),
-                    options.to_string(),
-                    max_run_num,
-                    maintain_blocks,
-                ),
-            )
-            .await?)
+        self.calls()
+            .n_node_un_specified_address_task(url, options, max_run_num, maintain_blocks)
+            .await
     }
 
     pub async fn n_nodespecified_address_task(
@@ -626,48 +589,35 @@ impl Client {
         receivers: Vec<Address>,
         maintain_blocks: u64,
     ) -> Result<TransactionReceipt, anyhow::Error> {
-        Ok(self
-            .write_contract(
-                "nNodespecifiedAddressTask",
-                (
-                    url.to_string(),
-                    options.to_string(),
-                    max_run_num,
-                    receivers,
-                    maintain_blocks,
-                ),
-            )
-            .await?)
+        self.calls()
+            .n_nodespecified_address_task(url, options, max_run_num, receivers, maintain_blocks)
+            .await
     }
 
     pub async fn race_sub_index_for_task(
         &self,
         task_id: u64,
     ) -> Result<TransactionReceipt, anyhow::Error> {
-        Ok(self
-            .write_contract("raceSubIndexForTask", (task_id,))
-            .await?)
+        self.calls().race_sub_index_for_task(task_id).await
     }
 
     pub async fn reset_runners(
         &self,
         receivers: Vec<Address>,
     ) -> Result<TransactionReceipt, anyhow::Error> {
-        Ok(self.write_contract("resetRunners", (receivers,)).await?)
+        self.calls().reset_runners(receivers).await
     }
 
     pub async fn stop_task(&self, task_id: u64) -> Result<TransactionReceipt, anyhow::Error> {
-        Ok(self.write_contract("stopTask", (task_id,)).await?)
+        self.calls().stop_task(task_id).await
     }
 
     pub async fn update_runner(&self, version: &str) -> Result<TransactionReceipt, anyhow::Error> {
-        Ok(self
-            .write_contract("updateRunner", (version.to_string(),))
-            .await?)
+        self.calls().update_runner(version).await
     }
 
     pub async fn withdraw_ezc(&self, task_id: u64) -> Result<TransactionReceipt, anyhow::Error> {
-        Ok(self.write_contract("withdrawEZC", (task_id,)).await?)
+        self.calls().withdraw_ezc(task_id).await
     }
 }
 
@@ -676,8 +626,11 @@ mod test {
 
     use super::*;
     use ethers::signers::LocalWallet;
+    use keys::SecretSource;
+    use network::Network;
     use simplelog::*;
     #[tokio::test]
+    #[ignore = "reads mainnet-dev with DEP_TEST_KEYSTORE and DEP_TEST_KEYSTORE_PASSWORD"]
     async fn test_read_contract() {
         CombinedLogger::init(vec![TermLogger::new(
             LevelFilter::Info,
@@ -686,15 +639,13 @@ mod test {
             ColorChoice::Auto,
         )])
         .expect("Failed to init logger");
-        let wallet = LocalWallet::decrypt_keystore("./eth.keystore", "VGPUmPKNtBzDvCJK").unwrap();
+        let keystore = std::env::var("DEP_TEST_KEYSTORE").expect("DEP_TEST_KEYSTORE is not set");
+        let password = SecretSource::Env("DEP_TEST_KEYSTORE_PASSWORD".to_string())
+            .read("")
+            .unwrap();
+        let wallet = LocalWallet::decrypt_keystore(keystore, password).unwrap();
         log::info!("{:x}", wallet.address());
-        let client = Client::new(
-            "https://mainnet-dev.deeper.network/rpc",
-            "9397AA12576cEc2A37C60f76d2FB31b31b5E5c7F",
-            "./testnet.json",
-            wallet,
-        )
-        .unwrap();
+        let client = Client::from_network(&Network::mainnet_dev(), wallet).unwrap();
         log::info!("{:?}", client.task_info(1).await.unwrap());
         let address = Address::from_str("27FdDEF298618B512Fa6D281DB0e32E0F38D15D3").unwrap();
         log::info!("{:?}", client.address_whitelist(address).await.unwrap());
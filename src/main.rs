@@ -0,0 +1,1359 @@
+//! `dep` command-line client.
+//!
+//! Reads need no keystore. Writes sign with `--keystore`; its password is
+//! read from `--password`, `DEP_KEYSTORE_PASSWORD` or a prompt. Dry runs
+//! check on behalf of `--from`, or of the address `--keystore` records,
+//! without decrypting it. `dep keys` manages the keystores in `--keys-dir`.
+
+use clap::{Parser, Subcommand};
+use dep::account::{evm_address, parse_ss58, AccountMapping, NodeAddress};
+use dep::control::{ControlMessage, ControlPlane};
+use dep::day::DepDay;
+use dep::events::{DepLog, EventWatcher};
+use dep::keys::{keystore_address, KeyInfo, KeyStore, SecretSource};
+use dep::network::Network;
+use dep::options::TaskOptions;
+use dep::rewards::format_ezc;
+use dep::rollout::NodeVersionStatus;
+use dep::whitelist::{ChangeState, DesiredWhitelist, WhitelistPlan};
+use dep::Client;
+use ethers::signers::LocalWallet;
+use serde_json::{json, Value};
+use simplelog::{ColorChoice, LevelFilter, TermLogger, TerminalMode};
+use std::path::PathBuf;
+use std::time::Duration;
+use web3::ethabi::Token;
+use web3::types::{Address, Bytes, TransactionReceipt};
+
+/// Signs read-only calls, which only use it as the `from` address.
+const READ_ONLY_KEY: &str = "0000000000000000000000000000000000000000000000000000000000000001";
+
+#[derive(Parser)]
+#[command(
+    name = "dep",
+    version,
+    about = "Command-line client for the DEP contract"
+)]
+struct Cli {
+    /// Built-in network name or TOML profile; defaults to the `DEP_*`
+    /// environment variables, then mainnet-dev.
+    #[arg(long, global = true)]
+    network: Option<String>,
+    /// Keystore that signs transactions: a path, or a key name in the keys
+    /// directory.
+    #[arg(long, global = true, env = "DEP_KEYSTORE")]
+    keystore: Option<PathBuf>,
+    /// Address dry runs check on behalf of; defaults to the address the
+    /// keystore records.
+    #[arg(long, global = true)]
+    from: Option<Address>,
+    /// Where the keystore password comes from: prompt, env:VAR, file:PATH or
+    /// keyring:NAME. Defaults to `DEP_KEYSTORE_PASSWORD` if set, else prompt.
+    #[arg(long, global = true)]
+    password: Option<SecretSource>,
+    /// Directory of keystores managed by `dep keys`.
+    #[arg(long, global = true, env = "DEP_KEYS_DIR", default_value = "keystore")]
+    keys_dir: PathBuf,
+    /// Print results as JSON.
+    #[arg(long, global = true)]
+    json: bool,
+    /// Log progress to stderr.
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// OTLP/gRPC collector to export contract call and transaction spans to.
+    #[cfg(feature = "otel")]
+    #[arg(long, global = true, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Contract parameters and the chain head.
+    Info,
+    /// The raw `taskInfo` of a task.
+    TaskInfo { task_id: u64 },
+    /// The lifecycle stage of a task.
+    TaskStatus { task_id: u64 },
+    /// The contract owner.
+    Owner,
+    /// Call any read-only contract function by its ABI name, e.g.
+    /// `call userTask 0x27fd... 7`.
+    Call {
+        /// Function name as in the ABI.
+        function: String,
+        /// Arguments in ABI order; addresses may be H160 or SS58.
+        args: Vec<String>,
+    },
+    /// A node's credit score and whether it may race.
+    Credit {
+        /// H160 or SS58 account.
+        node: NodeAddress,
+    },
+    /// The address whitelist for publishers.
+    #[command(subcommand)]
+    Whitelist(WhitelistCommand),
+    /// The image persistence whitelist.
+    #[command(subcommand)]
+    Image(ImageCommand),
+    /// A node's earnings per day, unsettled ones by default.
+    Rewards {
+        /// H160 or SS58 account.
+        node: NodeAddress,
+        /// First day, as YYYY-MM-DD or a day number.
+        #[arg(long)]
+        from: Option<DepDay>,
+        #[arg(long)]
+        to: Option<DepDay>,
+        /// Aggregate per week.
+        #[arg(long)]
+        weekly: bool,
+    },
+    /// Reconcile a day's node earnings against its reward pool.
+    Reconcile {
+        day: DepDay,
+        /// Print CSV instead of text.
+        #[arg(long)]
+        csv: bool,
+    },
+    /// Publish a task.
+    Publish {
+        #[arg(long)]
+        image: String,
+        /// Task options as JSON, or @path to a JSON file.
+        #[arg(long)]
+        options: Option<String>,
+        #[arg(long, default_value_t = 1)]
+        max_runs: u64,
+        /// Comma separated H160 or SS58 nodes; any node when omitted.
+        #[arg(long, value_delimiter = ',')]
+        receivers: Vec<NodeAddress>,
+        #[arg(long, default_value_t = 0)]
+        maintain_blocks: u64,
+        /// Validate and print the cost without publishing.
+        #[arg(long)]
+        dry_run: bool,
+        /// Publish without checking the EZC balance, for networks without a
+        /// known EZC address.
+        #[arg(long)]
+        skip_balance_check: bool,
+    },
+    /// Race for a run of a task.
+    Race { task_id: u64 },
+    /// Complete a raced run.
+    Complete { task_id: u64 },
+    /// Emit `StopTask` for a task.
+    Stop { task_id: u64 },
+    /// Refund a task's unused EZC to its publisher (owner only).
+    Withdraw {
+        #[arg(required_unless_present = "all")]
+        task_id: Option<u64>,
+        /// Refund every eligible task.
+        #[arg(long, conflicts_with = "task_id")]
+        all: bool,
+        /// With --all, start scanning at this task.
+        #[arg(long, default_value_t = 1)]
+        from_task: u64,
+        /// List refunds without sending anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Extend a task's maintained lifetime.
+    Extend {
+        task_id: u64,
+        blocks: u64,
+        /// Print the cost without extending.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Emit `ResetRunners` for the given H160 or SS58 nodes, or all nodes.
+    ResetRunners { receivers: Vec<NodeAddress> },
+    /// Announce a runner version (UPDATER_ROLE only). Malformed versions
+    /// and downgrades are refused.
+    UpdateRunner {
+        version: String,
+        /// Allow a version lower than the highest announced one.
+        #[arg(long)]
+        allow_downgrade: bool,
+        /// Defaults to the network's deployment block; required without one.
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// Only run the checks.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Runner version announcements and how a node compares.
+    Rollout {
+        /// Defaults to the network's deployment block; required without one.
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// A node's self-reported version to compare.
+        #[arg(long)]
+        node_version: Option<String>,
+    },
+    /// Contract events.
+    #[command(subcommand)]
+    Events(EventsCommand),
+    /// Serve chain state and RPC counters as Prometheus metrics.
+    #[cfg(feature = "metrics")]
+    Metrics {
+        #[arg(long, default_value = "127.0.0.1:9615")]
+        listen: std::net::SocketAddr,
+        /// Count completions from here; defaults to the network's
+        /// deployment block, or the current head without one.
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// H160 or SS58 addresses whose EZC balance to export.
+        #[arg(long = "ezc-holder")]
+        ezc_holders: Vec<NodeAddress>,
+        /// Seconds between chain state refreshes.
+        #[arg(long, default_value_t = 30)]
+        interval_secs: u64,
+    },
+    /// Keystores in the keys directory.
+    #[command(subcommand)]
+    Keys(KeysCommand),
+    /// Map an SS58 account to its EVM address, or an H160 to the substrate
+    /// account holding its balance, using the network profile's mapping.
+    Account { account: String },
+}
+
+#[derive(Subcommand)]
+enum WhitelistCommand {
+    /// Whether an address may publish tasks.
+    Check { address: Address },
+    /// Allow an address to publish tasks (owner only).
+    Add { address: Address },
+    /// Stop an address from publishing tasks (owner only).
+    Remove { address: Address },
+    /// Addresses set through `setAddressWhitelist`, from indexed calls.
+    List {
+        /// Defaults to the network's deployment block; required without one.
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// Defaults to the head.
+        #[arg(long)]
+        to_block: Option<u64>,
+    },
+    /// Compare a CSV or JSON allowlist with the chain and write a plan.
+    Diff {
+        /// Rows of `address[,allowed]`, or a JSON list or map.
+        file: PathBuf,
+        /// Also revoke whitelisted addresses the file does not list.
+        #[arg(long)]
+        prune: bool,
+        /// Where to write the plan for `whitelist apply`.
+        #[arg(long)]
+        plan: Option<PathBuf>,
+        /// Defaults to the network's deployment block; required without one.
+        #[arg(long)]
+        from_block: Option<u64>,
+        #[arg(long)]
+        to_block: Option<u64>,
+    },
+    /// Send a plan's remaining changes; re-run to resume (owner only).
+    Apply { plan: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum ImageCommand {
+    /// Whether an image is on the persistence whitelist.
+    Status { url: String },
+    /// Whitelisted images and who set them, replayed from events.
+    List {
+        /// Defaults to the network's deployment block; required without one.
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// Include images that are no longer whitelisted.
+        #[arg(long)]
+        all: bool,
+    },
+    /// Replace the wallet's persisted image, which un-whitelists its
+    /// previous one.
+    #[command(alias = "add")]
+    Replace {
+        url: String,
+        /// Defaults to the network's deployment block; required without one.
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// Send even if the previous image is taken off the whitelist.
+        #[arg(long)]
+        yes: bool,
+        /// Print the preview without sending.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Emit `DeleteImage` (owner only).
+    Delete { url: String },
+}
+
+#[derive(Subcommand)]
+enum EventsCommand {
+    /// Follow new events.
+    Tail {
+        /// Start here instead of the next block.
+        #[arg(long)]
+        from_block: Option<u64>,
+        #[arg(long, default_value_t = 6)]
+        interval_secs: u64,
+    },
+    /// Print past events.
+    Backfill {
+        /// Defaults to the network's deployment block; required without one.
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// Defaults to the head.
+        #[arg(long)]
+        to_block: Option<u64>,
+    },
+    /// Check past `StopTask` and `ResetRunners` events against their
+    /// senders, flagging unauthorized ones.
+    Control {
+        /// Defaults to the network's deployment block; required without one.
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// Defaults to the head.
+        #[arg(long)]
+        to_block: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeysCommand {
+    /// Generate a key.
+    New {
+        /// Defaults to the key's address.
+        #[arg(long)]
+        name: Option<String>,
+        /// Source of the new key's password.
+        #[arg(long, default_value = "prompt")]
+        new_password: SecretSource,
+    },
+    /// Import a hex private key or a mnemonic phrase.
+    Import {
+        #[arg(long)]
+        name: Option<String>,
+        /// Source of a hex private key.
+        #[arg(long, required_unless_present = "mnemonic")]
+        private_key: Option<SecretSource>,
+        /// Source of a BIP-39 phrase.
+        #[arg(long, conflicts_with = "private_key")]
+        mnemonic: Option<SecretSource>,
+        /// Account index under m/44'/60'/0'/0.
+        #[arg(long, default_value_t = 0, requires = "mnemonic")]
+        index: u32,
+        #[arg(long, default_value = "prompt")]
+        new_password: SecretSource,
+    },
+    /// List keys and their addresses.
+    List,
+    /// Print a key's address.
+    Address { name: String },
+    /// Re-encrypt a key; the current password comes from --password.
+    ChangePassword {
+        name: String,
+        #[arg(long, default_value = "prompt")]
+        new_password: SecretSource,
+    },
+}
+
+impl Command {
+    fn signs(&self) -> bool {
+        match self {
+            Command::Whitelist(command) => matches!(
+                command,
+                WhitelistCommand::Add { .. }
+                    | WhitelistCommand::Remove { .. }
+                    | WhitelistCommand::Apply { .. }
+            ),
+            Command::Image(command) => match command {
+                ImageCommand::Status { .. } | ImageCommand::List { .. } => false,
+                ImageCommand::Replace { dry_run, .. } => !dry_run,
+                ImageCommand::Delete { .. } => true,
+            },
+            Command::Publish { dry_run, .. }
+            | Command::Withdraw { dry_run, .. }
+            | Command::Extend { dry_run, .. }
+            | Command::UpdateRunner { dry_run, .. } => !dry_run,
+            Command::Race { .. }
+            | Command::Complete { .. }
+            | Command::Stop { .. }
+            | Command::ResetRunners { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the command is a dry run that checks on behalf of the
+    /// operator's address.
+    fn checks_operator(&self) -> bool {
+        matches!(
+            self,
+            Command::Publish { dry_run: true, .. }
+                | Command::Image(ImageCommand::Replace { dry_run: true, .. })
+        )
+    }
+}
+
+fn network(name: Option<&str>) -> Result<Network, anyhow::Error> {
+    match name {
+        Some(name) => Network::load(name),
+        None if std::env::var_os("DEP_NETWORK").is_some()
+            || std::env::var_os("DEP_RPC_URLS").is_some() =>
+        {
+            Network::from_env()
+        }
+        None => Ok(Network::mainnet_dev()),
+    }
+}
+
+fn password(cli: &Cli, keystore: &str) -> Result<String, anyhow::Error> {
+    let source = match &cli.password {
+        Some(source) => source.clone(),
+        None if std::env::var_os("DEP_KEYSTORE_PASSWORD").is_some() => {
+            SecretSource::Env("DEP_KEYSTORE_PASSWORD".to_string())
+        }
+        None => SecretSource::Prompt,
+    };
+    source.read(&format!("Password for {}: ", keystore))
+}
+
+fn keystore_path(cli: &Cli) -> Result<PathBuf, anyhow::Error> {
+    let keystore = match &cli.keystore {
+        Some(keystore) => keystore,
+        None => {
+            anyhow::bail!("this command signs transactions, pass --keystore or set DEP_KEYSTORE")
+        }
+    };
+    // a bare name that is not a file refers to the keys directory
+    if !keystore.exists() && keystore.components().count() == 1 {
+        Ok(cli.keys_dir.join(keystore))
+    } else {
+        Ok(keystore.clone())
+    }
+}
+
+fn wallet(cli: &Cli) -> Result<LocalWallet, anyhow::Error> {
+    if !cli.command.signs() {
+        return Ok(READ_ONLY_KEY.parse()?);
+    }
+    let keystore = keystore_path(cli)?;
+    let password = password(cli, &keystore.display().to_string())?;
+    Ok(LocalWallet::decrypt_keystore(&keystore, password)?)
+}
+
+/// The address a dry run checks on behalf of: `--from`, else the address
+/// the keystore records.
+fn dry_run_address(cli: &Cli) -> Result<Address, anyhow::Error> {
+    match cli.from {
+        Some(address) => Ok(address),
+        None if cli.keystore.is_none() => {
+            anyhow::bail!("dry runs check on behalf of an address, pass --from or --keystore")
+        }
+        None => keystore_address(&keystore_path(cli)?),
+    }
+}
+
+fn emit_key(json: bool, key: &KeyInfo) {
+    let address = key
+        .address
+        .map(|address| format!("{:?}", address))
+        .unwrap_or_else(|| "unknown".to_string());
+    emit(
+        json,
+        json!({
+            "name": key.name,
+            "address": key.address,
+            "path": key.path,
+        }),
+        || format!("{}\t{}", key.name, address),
+    );
+}
+
+fn keys(cli: &Cli, command: &KeysCommand) -> Result<(), anyhow::Error> {
+    let store = KeyStore::open(&cli.keys_dir)?;
+    let json = cli.json;
+    match command {
+        KeysCommand::New { name, new_password } => {
+            let password = new_password.read_new("New password: ")?;
+            emit_key(json, &store.create(name.as_deref(), &password)?);
+        }
+        KeysCommand::Import {
+            name,
+            private_key,
+            mnemonic,
+            index,
+            new_password,
+        } => {
+            let key = match (private_key, mnemonic) {
+                (Some(source), _) => {
+                    let private_key = source.read("Private key: ")?;
+                    let password = new_password.read_new("New password: ")?;
+                    store.import_private_key(name.as_deref(), &private_key, &password)?
+                }
+                (None, Some(source)) => {
+                    let phrase = source.read("Mnemonic: ")?;
+                    let password = new_password.read_new("New password: ")?;
+                    store.import_mnemonic(name.as_deref(), &phrase, *index, &password)?
+                }
+                (None, None) => anyhow::bail!("--private-key or --mnemonic is required"),
+            };
+            emit_key(json, &key);
+        }
+        KeysCommand::List => {
+            for key in store.list()? {
+                emit_key(json, &key);
+            }
+        }
+        KeysCommand::Address { name } => {
+            let address = store.address(name, || password(cli, name))?;
+            emit(json, json!({ "name": name, "address": address }), || {
+                format!("{:?}", address)
+            });
+        }
+        KeysCommand::ChangePassword { name, new_password } => {
+            let old_password = password(cli, name)?;
+            store.unlock(name, &old_password)?;
+            let password = new_password.read_new("New password: ")?;
+            emit_key(
+                json,
+                &store.change_password(name, &old_password, &password)?,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Prints `value` as JSON or `text` as is.
+fn emit(json: bool, value: Value, text: impl FnOnce() -> String) {
+    if json {
+        println!("{}", value);
+    } else {
+        println!("{}", text());
+    }
+}
+
+/// A decoded contract value as JSON; integers are decimal strings.
+fn token_json(token: &Token) -> Value {
+    match token {
+        Token::Address(address) => json!(address),
+        Token::Uint(value) | Token::Int(value) => json!(value.to_string()),
+        Token::Bool(value) => json!(value),
+        Token::String(value) => json!(value),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => json!(Bytes(bytes.clone())),
+        Token::Array(tokens) | Token::FixedArray(tokens) | Token::Tuple(tokens) => {
+            Value::Array(tokens.iter().map(token_json).collect())
+        }
+    }
+}
+
+fn emit_receipt(json: bool, receipt: &TransactionReceipt) -> Result<(), anyhow::Error> {
+    let succeeded = receipt.status == Some(1.into());
+    emit(
+        json,
+        json!({
+            "transaction_hash": receipt.transaction_hash,
+            "block_number": receipt.block_number,
+            "status": succeeded,
+            "gas_used": receipt.gas_used,
+        }),
+        || {
+            format!(
+                "{} in block {} ({})",
+                if succeeded { "succeeded" } else { "reverted" },
+                receipt.block_number.unwrap_or_default(),
+                format_args!("{:?}", receipt.transaction_hash)
+            )
+        },
+    );
+    if !succeeded {
+        anyhow::bail!("transaction reverted");
+    }
+    Ok(())
+}
+
+fn emit_events(json: bool, logs: &[DepLog]) {
+    for log in logs {
+        emit(
+            json,
+            json!({
+                "block_number": log.block_number,
+                "transaction_hash": log.transaction_hash,
+                "log_index": log.log_index,
+                "event": log.event,
+            }),
+            || {
+                format!(
+                    "{}\t{:?}\t{:?}",
+                    log.block_number, log.transaction_hash, log.event
+                )
+            },
+        );
+    }
+}
+
+fn account(json: bool, input: &str, mapping: &AccountMapping) -> Result<(), anyhow::Error> {
+    let input = input.trim();
+    let hex = input.strip_prefix("0x").unwrap_or(input);
+    if hex.len() == 40 {
+        let address: Address = hex.parse()?;
+        let account = mapping.to_ss58(&mapping.substrate_account(address));
+        emit(
+            json,
+            json!({ "evm_address": address, "substrate_account": account }),
+            || format!("{:?} holds its balance in {}", address, account),
+        );
+    } else {
+        let account = parse_ss58(input)?;
+        let address = evm_address(&account);
+        emit(
+            json,
+            json!({ "account": mapping.to_ss58(&account), "evm_address": address }),
+            || format!("{} acts as {:?}", mapping.to_ss58(&account), address),
+        );
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
+    let level = if cli.verbose {
+        LevelFilter::Info
+    } else {
+        LevelFilter::Warn
+    };
+    TermLogger::init(
+        level,
+        simplelog::Config::default(),
+        TerminalMode::Stderr,
+        ColorChoice::Auto,
+    )?;
+    #[cfg(feature = "otel")]
+    let _telemetry = match &cli.otlp_endpoint {
+        Some(endpoint) => Some(dep::telemetry::init(
+            endpoint,
+            tracing::level_filters::LevelFilter::DEBUG,
+        )?),
+        None => None,
+    };
+    match &cli.command {
+        Command::Keys(command) => return keys(&cli, command),
+        Command::Account { account: input } => {
+            let network = network(cli.network.as_deref())?;
+            return account(cli.json, input, &network.accounts);
+        }
+        _ => {}
+    }
+    let network = network(cli.network.as_deref())?;
+    let client = Client::connect(&network, wallet(&cli)?).await?;
+    let operator = if cli.command.checks_operator() {
+        dry_run_address(&cli)?
+    } else {
+        client.address()
+    };
+    let json = cli.json;
+
+    match cli.command {
+        Command::Info => {
+            let head = client.snapshot().await?;
+            let head = head.calls();
+            let value = json!({
+                "network": network.name,
+                "dep_address": network.dep_address,
+                "implementation_version": head.implementation_version().await?,
+                "owner": head.owner().await?,
+                "task_sum": head.task_sum().await?,
+                "proof_unit": format_ezc(head.proof_unit().await?),
+                "block_uint_price": head.block_uint_price().await?,
+                "race_timeout": head.race_timeout().await?,
+                "complete_timeout": head.complete_timeout().await?,
+                "credit_threshold": head.credit_threshold().await?,
+                "start_day": DepDay(head.start_day().await?).to_string(),
+                "current_day": DepDay(head.get_current_day().await?).to_string(),
+            });
+            emit(json, value.clone(), || {
+                value
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            });
+        }
+        Command::Call { function, args } => {
+            let outputs = client.calls().call(&function, &args).await?;
+            let outputs: Vec<(String, Value)> = outputs
+                .iter()
+                .map(|(name, token)| (name.clone(), token_json(token)))
+                .collect();
+            let value = Value::Object(outputs.iter().cloned().collect());
+            emit(json, value, || {
+                outputs
+                    .iter()
+                    .map(|(name, value)| match value.as_str() {
+                        Some(text) => format!("{}: {}", name, text),
+                        None => format!("{}: {}", name, value),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            });
+        }
+        Command::TaskInfo { task_id } => {
+            let info = client.calls().task_info(task_id).await?;
+            emit(json, serde_json::to_value(&info)?, || {
+                format!("{:#?}", info)
+            });
+        }
+        Command::TaskStatus { task_id } => {
+            let status = client.task_status(task_id).await?;
+            let next = status.next_transition().map(|wait| wait.as_secs());
+            emit(
+                json,
+                json!({
+                    "status": format!("{:?}", status),
+                    "final": status.is_final(),
+                    "next_transition_secs": next,
+                }),
+                || match next {
+                    Some(secs) => format!("{:?}, changes in {}s", status, secs),
+                    None => format!("{:?}", status),
+                },
+            );
+        }
+        Command::Owner => {
+            let owner = client.calls().owner().await?;
+            emit(json, json!(owner), || format!("{:?}", owner));
+        }
+        Command::Credit { node } => {
+            let credit = client.credit_check(node.0).await?;
+            emit(
+                json,
+                json!({
+                    "node": node.0,
+                    "score": credit.score,
+                    "threshold": credit.threshold,
+                    "eligible": credit.eligible(),
+                }),
+                || {
+                    format!(
+                        "score {}, threshold {}: {}",
+                        credit.score,
+                        credit.threshold,
+                        if credit.eligible() {
+                            "may race"
+                        } else {
+                            "races revert with \"Low credit score\""
+                        }
+                    )
+                },
+            );
+        }
+        Command::Whitelist(WhitelistCommand::Check { address }) => {
+            let whitelisted = client.calls().address_whitelist(address).await?;
+            emit(json, json!(whitelisted), || whitelisted.to_string());
+        }
+        Command::Whitelist(WhitelistCommand::Add { address }) => {
+            let receipt = client.calls().set_address_whitelist(address, true).await?;
+            emit_receipt(json, &receipt)?;
+        }
+        Command::Whitelist(WhitelistCommand::Remove { address }) => {
+            let receipt = client.calls().set_address_whitelist(address, false).await?;
+            emit_receipt(json, &receipt)?;
+        }
+        Command::Whitelist(WhitelistCommand::List {
+            from_block,
+            to_block,
+        }) => {
+            let to_block = match to_block {
+                Some(block) => block,
+                None => client.block_number().await?,
+            };
+            let history = client
+                .whitelist_history(network.start_block(from_block)?, to_block)
+                .await?;
+            for (address, record) in history {
+                emit(
+                    json,
+                    json!({
+                        "address": address,
+                        "allowed": record.allowed,
+                        "block_number": record.block_number,
+                        "transaction_hash": record.transaction_hash,
+                    }),
+                    || {
+                        format!(
+                            "{:?}\t{}\tblock {}",
+                            address,
+                            if record.allowed { "allowed" } else { "revoked" },
+                            record.block_number
+                        )
+                    },
+                );
+            }
+        }
+        Command::Whitelist(WhitelistCommand::Diff {
+            file,
+            prune,
+            plan,
+            from_block,
+            to_block,
+        }) => {
+            let desired = DesiredWhitelist::load(&file)?;
+            let history = if prune {
+                let to_block = match to_block {
+                    Some(block) => block,
+                    None => client.block_number().await?,
+                };
+                client
+                    .whitelist_history(network.start_block(from_block)?, to_block)
+                    .await?
+            } else {
+                Default::default()
+            };
+            let diff = client.whitelist_diff(&desired, &history, prune).await?;
+            emit(
+                json,
+                json!({
+                    "grant": diff.grant,
+                    "revoke": diff.revoke,
+                    "unchanged": diff.unchanged,
+                }),
+                || {
+                    let mut lines: Vec<_> = diff
+                        .grant
+                        .iter()
+                        .map(|address| format!("+ {:?}", address))
+                        .chain(diff.revoke.iter().map(|address| format!("- {:?}", address)))
+                        .collect();
+                    lines.push(format!(
+                        "{} to grant, {} to revoke, {} unchanged",
+                        diff.grant.len(),
+                        diff.revoke.len(),
+                        diff.unchanged
+                    ));
+                    lines.join("\n")
+                },
+            );
+            if let Some(path) = plan {
+                WhitelistPlan::from_diff(&diff).save(&path)?;
+                eprintln!("wrote {}", path.display());
+            }
+        }
+        Command::Whitelist(WhitelistCommand::Apply { plan: path }) => {
+            let mut plan = WhitelistPlan::load(&path)?;
+            client
+                .apply_whitelist_plan(&mut plan, Some(&path), |done, total, change| {
+                    let outcome = match &change.state {
+                        ChangeState::Applied {
+                            transaction_hash: Some(hash),
+                        } => format!("{:?}", hash),
+                        ChangeState::Applied {
+                            transaction_hash: None,
+                        } => "already set".to_string(),
+                        ChangeState::Failed { reason } => format!("failed: {}", reason),
+                        ChangeState::Pending => "pending".to_string(),
+                    };
+                    eprintln!(
+                        "[{}/{}] {} {:?}: {}",
+                        done,
+                        total,
+                        if change.allow { "grant" } else { "revoke" },
+                        change.address,
+                        outcome
+                    );
+                })
+                .await?;
+            let failed = plan.failed().count();
+            emit(
+                json,
+                json!({ "remaining": plan.remaining(), "failed": failed }),
+                || format!("{} changes remaining", plan.remaining()),
+            );
+            if failed > 0 {
+                anyhow::bail!("{} changes failed; re-run to retry them", failed);
+            }
+        }
+        Command::Image(ImageCommand::Status { url }) => {
+            let whitelisted = client.calls().image_white_list_status(&url).await?;
+            emit(json, json!(whitelisted), || whitelisted.to_string());
+        }
+        Command::Image(ImageCommand::List { from_block, all }) => {
+            let registry = client
+                .image_registry(network.start_block(from_block)?)
+                .await?;
+            for entry in registry.entries() {
+                if !all && !entry.whitelisted {
+                    continue;
+                }
+                emit(
+                    json,
+                    json!({
+                        "url": entry.url,
+                        "whitelisted": entry.whitelisted,
+                        "set_by": entry.set_by,
+                        "last_set_by": entry.last_set.sender,
+                        "last_set_block": entry.last_set.block_number,
+                        "last_set_transaction": entry.last_set.transaction_hash,
+                        "delete_requested_at": entry.delete_requested_at,
+                    }),
+                    || {
+                        let mut line = format!(
+                            "{}\t{}\tset by {:?} in block {}",
+                            entry.url,
+                            if entry.whitelisted {
+                                "whitelisted"
+                            } else {
+                                "not whitelisted"
+                            },
+                            entry.set_by,
+                            entry.last_set.block_number
+                        );
+                        if entry.orphaned() {
+                            line.push_str("\t(un-whitelisted by another address's replace)");
+                        }
+                        if let Some(block) = entry.delete_requested_at {
+                            line.push_str(&format!("\tDeleteImage in block {}", block));
+                        }
+                        line
+                    },
+                );
+            }
+        }
+        Command::Image(ImageCommand::Replace {
+            url,
+            from_block,
+            yes,
+            dry_run,
+        }) => {
+            let registry = client
+                .image_registry(network.start_block(from_block)?)
+                .await?;
+            let preview = client
+                .preview_replace_image(operator, &url, &registry)
+                .await?;
+            let warnings = preview.warnings();
+            if json {
+                println!(
+                    "{}",
+                    json!({
+                        "caller": preview.caller,
+                        "url": preview.url,
+                        "current": preview.current,
+                        "unwhitelisted": preview.unwhitelisted,
+                        "shared_with": preview.shared_with,
+                        "warnings": warnings,
+                    })
+                );
+            } else {
+                println!(
+                    "{} -> {}",
+                    preview.current.as_deref().unwrap_or("(none)"),
+                    preview.url
+                );
+                for warning in &warnings {
+                    eprintln!("warning: {}", warning);
+                }
+            }
+            if dry_run {
+                return Ok(());
+            }
+            if preview.is_destructive() && !yes {
+                anyhow::bail!("replacing would un-whitelist an image; re-run with --yes");
+            }
+            emit_receipt(json, &client.replace_image(&preview).await?)?;
+        }
+        Command::Image(ImageCommand::Delete { url }) => {
+            emit_receipt(json, &client.delete_image(&url).await?)?;
+        }
+        Command::Rewards {
+            node,
+            from,
+            to,
+            weekly,
+        } => {
+            let summary = match (from, to) {
+                (None, None) => client.unsettled_rewards(node.0).await?,
+                (from, to) => {
+                    let to = match to {
+                        Some(to) => to,
+                        None => client.current_day().await?,
+                    };
+                    client
+                        .rewards_between(node.0, from.unwrap_or(to), to)
+                        .await?
+                }
+            };
+            if weekly {
+                let weeks = summary.by_week();
+                let value = weeks
+                    .iter()
+                    .map(|week| {
+                        json!({
+                            "week_start": week.week_start.to_string(),
+                            "earned": format_ezc(week.earned),
+                            "day_total": format_ezc(week.day_total),
+                        })
+                    })
+                    .collect();
+                emit(json, Value::Array(value), || {
+                    weeks
+                        .iter()
+                        .map(|week| {
+                            format!(
+                                "{}\t{}\t{}",
+                                week.week_start,
+                                format_ezc(week.earned),
+                                format_ezc(week.day_total)
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                });
+            } else {
+                let value = json!({
+                    "node": summary.node,
+                    "total": format_ezc(summary.total()),
+                    "days": summary.days.iter().map(|day| json!({
+                        "day": day.day.to_string(),
+                        "earned": format_ezc(day.earned),
+                        "day_total": format_ezc(day.day_total),
+                        "share": day.share(),
+                    })).collect::<Vec<_>>(),
+                });
+                emit(json, value, || summary.to_string());
+            }
+        }
+        Command::Reconcile { day, csv } => {
+            let report = client.reconcile_day(day).await?;
+            if csv {
+                print!("{}", report.to_csv());
+            } else {
+                let value = report.to_json();
+                emit(json, value.clone(), || {
+                    serde_json::to_string_pretty(&value).unwrap_or_default()
+                });
+            }
+        }
+        Command::Publish {
+            image,
+            options,
+            max_runs,
+            receivers,
+            maintain_blocks,
+            dry_run,
+            skip_balance_check,
+        } => {
+            let options = match options {
+                Some(options) => match options.strip_prefix('@') {
+                    Some(path) => TaskOptions::from_json(&std::fs::read_to_string(path)?)?,
+                    None => TaskOptions::from_json(&options)?,
+                },
+                None => TaskOptions::default(),
+            };
+            let mut builder = client
+                .task_builder()
+                .image(&image)
+                .options(options)
+                .max_runs(max_runs)
+                .receivers(receivers)
+                .maintain_blocks(maintain_blocks);
+            if skip_balance_check {
+                builder = builder.skip_balance_check();
+            }
+            if dry_run {
+                builder = builder.publisher(operator);
+                let cost = builder.validate().await?;
+                emit(json, json!({ "cost": format_ezc(cost) }), || {
+                    format!("valid, costs {} EZC", format_ezc(cost))
+                });
+            } else {
+                let task = builder.publish().await?;
+                emit(
+                    json,
+                    json!({
+                        "task_id": task.task_id,
+                        "cost": format_ezc(task.cost),
+                        "transaction_hash": task.receipt.transaction_hash,
+                    }),
+                    || {
+                        format!(
+                            "published task {} for {} EZC",
+                            task.task_id,
+                            format_ezc(task.cost)
+                        )
+                    },
+                );
+            }
+        }
+        Command::Race { task_id } => {
+            emit_receipt(json, &client.race_sub_index_for_task(task_id).await?)?;
+        }
+        Command::Complete { task_id } => {
+            emit_receipt(json, &client.complete_sub_index_for_task(task_id).await?)?;
+        }
+        Command::Stop { task_id } => {
+            emit_receipt(json, &client.stop_task(task_id).await?)?;
+        }
+        Command::Withdraw {
+            task_id: Some(task_id),
+            dry_run: false,
+            ..
+        } => {
+            emit_receipt(json, &client.withdraw_ezc(task_id).await?)?;
+        }
+        Command::Withdraw {
+            task_id,
+            from_task,
+            dry_run: true,
+            ..
+        } => {
+            let from_task = task_id.unwrap_or(from_task);
+            let mut scan = client.pending_refunds(from_task).await?;
+            if let Some(task_id) = task_id {
+                scan.refunds.retain(|refund| refund.task_id == task_id);
+            }
+            for refund in &scan.refunds {
+                emit(
+                    json,
+                    json!({
+                        "task_id": refund.task_id,
+                        "publisher": refund.publisher,
+                        "refund": format_ezc(refund.amount),
+                    }),
+                    || {
+                        format!(
+                            "{}\t{:?}\t{}",
+                            refund.task_id,
+                            refund.publisher,
+                            format_ezc(refund.amount)
+                        )
+                    },
+                );
+            }
+        }
+        Command::Withdraw { from_task, .. } => {
+            let report = client.sweep_refunds(from_task).await?;
+            let publishers = report.by_publisher();
+            let value = json!({
+                "total": format_ezc(report.total()),
+                "resume_from": report.resume_from,
+                "publishers": publishers.iter().map(|(publisher, refunds)| json!({
+                    "publisher": publisher,
+                    "task_ids": refunds.task_ids,
+                    "total": format_ezc(refunds.total),
+                })).collect::<Vec<_>>(),
+                "failed": report.failed.iter().map(|(refund, reason)| json!({
+                    "task_id": refund.task_id,
+                    "reason": reason,
+                })).collect::<Vec<_>>(),
+            });
+            emit(json, value, || {
+                let mut lines: Vec<String> = publishers
+                    .iter()
+                    .map(|(publisher, refunds)| {
+                        format!(
+                            "{:?}\t{}\t{:?}",
+                            publisher,
+                            format_ezc(refunds.total),
+                            refunds.task_ids
+                        )
+                    })
+                    .collect();
+                for (refund, reason) in &report.failed {
+                    lines.push(format!("task {} failed: {}", refund.task_id, reason));
+                }
+                lines.push(format!(
+                    "refunded {} EZC, next sweep from task {}",
+                    format_ezc(report.total()),
+                    report.resume_from
+                ));
+                lines.join("\n")
+            });
+        }
+        Command::Extend {
+            task_id,
+            blocks,
+            dry_run,
+        } => {
+            if dry_run {
+                let cost = client.estimate_extension_cost(task_id, blocks).await?;
+                emit(json, json!({ "cost": format_ezc(cost) }), || {
+                    format!("costs {} EZC", format_ezc(cost))
+                });
+            } else {
+                emit_receipt(json, &client.increase_task_duration(task_id, blocks).await?)?;
+            }
+        }
+        Command::ResetRunners { receivers } => {
+            emit_receipt(
+                json,
+                &client
+                    .reset_runners(receivers.into_iter().map(Address::from).collect())
+                    .await?,
+            )?;
+        }
+        Command::UpdateRunner {
+            version,
+            allow_downgrade,
+            from_block,
+            dry_run,
+        } => {
+            let history = client
+                .runner_history(network.start_block(from_block)?)
+                .await?;
+            if dry_run {
+                let next = history.check_next(&version, allow_downgrade)?;
+                emit(json, json!({ "version": next.to_string() }), || {
+                    format!("{} can be announced", next)
+                });
+            } else {
+                let receipt = client
+                    .announce_runner_version(&version, &history, allow_downgrade)
+                    .await?;
+                emit_receipt(json, &receipt)?;
+            }
+        }
+        Command::Rollout {
+            from_block,
+            node_version,
+        } => {
+            let history = client
+                .runner_history(network.start_block(from_block)?)
+                .await?;
+            for announcement in &history.announcements {
+                emit(
+                    json,
+                    json!({
+                        "version": announcement.version,
+                        "valid": announcement.parsed.is_some(),
+                        "block_number": announcement.block_number,
+                        "timestamp": announcement.timestamp,
+                        "transaction_hash": announcement.transaction_hash,
+                    }),
+                    || {
+                        format!(
+                            "{}\tblock {}\t{}{}",
+                            announcement.version,
+                            announcement.block_number,
+                            announcement.timestamp,
+                            if announcement.parsed.is_none() {
+                                "\t(malformed)"
+                            } else {
+                                ""
+                            }
+                        )
+                    },
+                );
+            }
+            if let Some(node_version) = node_version {
+                let status = history.compare(&node_version);
+                let text = match &status {
+                    NodeVersionStatus::UpToDate => "up to date".to_string(),
+                    NodeVersionStatus::Behind { latest } => format!("behind {}", latest),
+                    NodeVersionStatus::Ahead { latest } => format!("ahead of {}", latest),
+                    NodeVersionStatus::Malformed { reason } => reason.clone(),
+                    NodeVersionStatus::NoAnnouncement => "nothing announced".to_string(),
+                };
+                emit(
+                    json,
+                    json!({ "node_version": node_version, "status": text }),
+                    || format!("{}: {}", node_version, text),
+                );
+            }
+        }
+        Command::Events(EventsCommand::Tail {
+            from_block,
+            interval_secs,
+        }) => {
+            let from_block = match from_block {
+                Some(block) => block,
+                None => client.block_number().await? + 1,
+            };
+            let mut watcher = EventWatcher::new(&client, from_block);
+            loop {
+                let batch = watcher.poll().await?;
+                emit_events(json, &batch.events());
+                if watcher.next_block() > client.block_number().await? {
+                    tokio::time::sleep(Duration::from_secs(interval_secs)).await;
+                }
+            }
+        }
+        Command::Events(EventsCommand::Backfill {
+            from_block,
+            to_block,
+        }) => {
+            let from_block = network.start_block(from_block)?;
+            let to_block = match to_block {
+                Some(block) => block,
+                None => client.block_number().await?,
+            };
+            emit_events(json, &client.events_between(from_block, to_block).await?);
+        }
+        Command::Events(EventsCommand::Control {
+            from_block,
+            to_block,
+        }) => {
+            let from_block = network.start_block(from_block)?;
+            let to_block = match to_block {
+                Some(block) => block,
+                None => client.block_number().await?,
+            };
+            let mut plane = ControlPlane::new(&client);
+            for log in client.events_between(from_block, to_block).await? {
+                let (value, text) = match plane.authenticate(&log).await? {
+                    Some(ControlMessage::Authorized {
+                        action,
+                        sender,
+                        authority,
+                        ..
+                    }) => (
+                        json!({
+                            "authorized": true,
+                            "action": format!("{:?}", action),
+                            "sender": sender,
+                            "authority": format!("{:?}", authority),
+                        }),
+                        format!("ok\t{:?}\tby {:?} ({:?})", action, sender, authority),
+                    ),
+                    Some(ControlMessage::Rejected { sender, reason, .. }) => (
+                        json!({
+                            "authorized": false,
+                            "event": log.event,
+                            "sender": sender,
+                            "reason": reason,
+                        }),
+                        format!("REJECTED\t{}", reason),
+                    ),
+                    None => continue,
+                };
+                emit(
+                    json,
+                    json!({
+                        "block_number": log.block_number,
+                        "transaction_hash": log.transaction_hash,
+                        "control": value,
+                    }),
+                    || format!("{}\t{:?}\t{}", log.block_number, log.transaction_hash, text),
+                );
+            }
+        }
+        #[cfg(feature = "metrics")]
+        Command::Metrics {
+            listen,
+            from_block,
+            ezc_holders,
+            interval_secs,
+        } => {
+            let config = dep::metrics::MetricsConfig {
+                from_block: from_block.or(network.deployment_block),
+                ezc_holders: ezc_holders.into_iter().map(Into::into).collect(),
+            };
+            let collector = dep::metrics::ChainCollector::new(&client, config)?;
+            dep::metrics::serve(collector, listen, Duration::from_secs(interval_secs)).await?;
+        }
+        Command::Keys(_) | Command::Account { .. } => unreachable!("handled before connecting"),
+    }
+    Ok(())
+}
@@ -0,0 +1,563 @@
+//! Prometheus exporter for DEP chain state, behind the `metrics` feature.
+//!
+//! A background task refreshes the chain state once per interval, reading
+//! the contract at the head block: `taskSum`, the start time of the last
+//! task, the lifecycle stage of every task that can still change, today's
+//! `dayTotalReward`, the contract parameters and the EZC balances of the
+//! configured addresses. Completions and rewards per node are counted from
+//! `CompleteTask` events since `from_block`. The RPC counters of the `Client`
+//! are exported as they stand after the refresh. Scrapes of `/metrics` only
+//! return the last refresh, so they never touch the node.
+//!
+//! Tasks that reached a final stage are remembered and never read again, so
+//! only the first refresh reads every task.
+
+use crate::events::{DepEvent, EventWatcher};
+use crate::rewards::format_ezc;
+use crate::status::TaskStatus;
+use crate::transport::{MethodStats, LATENCY_BUCKETS};
+use crate::Client;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use web3::types::{Address, BlockId, BlockNumber, U256};
+
+/// The `Content-Type` of the text exposition format.
+pub const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Histogram => "histogram",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// The family name plus a suffix such as `_bucket`, if any.
+    pub name: String,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricFamily {
+    pub name: String,
+    pub help: String,
+    pub kind: MetricKind,
+    pub samples: Vec<Sample>,
+}
+
+impl MetricFamily {
+    pub fn push(&mut self, labels: &[(&str, &str)], value: f64) {
+        self.push_suffixed("", labels, value);
+    }
+
+    pub fn push_suffixed(&mut self, suffix: &str, labels: &[(&str, &str)], value: f64) {
+        self.samples.push(Sample {
+            name: format!("{}{}", self.name, suffix),
+            labels: labels
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            value,
+        });
+    }
+}
+
+/// Metric families in the order they are rendered.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Exposition {
+    pub families: Vec<MetricFamily>,
+}
+
+impl Exposition {
+    /// Adds an empty family and returns it for its samples.
+    pub fn family(&mut self, name: &str, kind: MetricKind, help: &str) -> &mut MetricFamily {
+        self.families.push(MetricFamily {
+            name: name.to_string(),
+            help: help.to_string(),
+            kind,
+            samples: vec![],
+        });
+        self.families.last_mut().unwrap()
+    }
+
+    /// A family with a single unlabelled sample.
+    pub fn single(&mut self, name: &str, kind: MetricKind, help: &str, value: f64) {
+        self.family(name, kind, help).push(&[], value);
+    }
+
+    /// Renders the families in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        for family in &self.families {
+            let help = family.help.replace('\\', "\\\\").replace('\n', "\\n");
+            writeln!(text, "# HELP {} {}", family.name, help).unwrap();
+            writeln!(text, "# TYPE {} {}", family.name, family.kind.as_str()).unwrap();
+            for sample in &family.samples {
+                text.push_str(&sample.name);
+                if !sample.labels.is_empty() {
+                    let labels: Vec<_> = sample
+                        .labels
+                        .iter()
+                        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+                        .collect();
+                    write!(text, "{{{}}}", labels.join(",")).unwrap();
+                }
+                writeln!(text, " {}", format_value(sample.value)).unwrap();
+            }
+        }
+        text
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// A raw EZC amount in whole EZC.
+pub fn ezc(amount: U256) -> f64 {
+    format_ezc(amount).parse().unwrap_or(f64::NAN)
+}
+
+/// The `status` label of a task's lifecycle stage.
+pub fn status_label(status: &TaskStatus) -> &'static str {
+    match status {
+        TaskStatus::Racing { .. } => "open",
+        TaskStatus::Filled { .. } => "filled",
+        TaskStatus::Running { .. } => "running",
+        TaskStatus::Completed { .. } => "completed",
+        TaskStatus::Expired { .. } => "expired",
+        TaskStatus::Withdrawable { .. } => "withdrawable",
+        TaskStatus::Withdrawn => "withdrawn",
+    }
+}
+
+const STATUS_LABELS: [&str; 7] = [
+    "open",
+    "filled",
+    "running",
+    "completed",
+    "expired",
+    "withdrawable",
+    "withdrawn",
+];
+
+/// Adds the request, error and latency counters of a client's RPCs.
+pub fn push_rpc_metrics(exposition: &mut Exposition, stats: &BTreeMap<String, MethodStats>) {
+    let requests = exposition.family(
+        "dep_rpc_requests_total",
+        MetricKind::Counter,
+        "JSON-RPC requests sent, by method.",
+    );
+    for (method, stats) in stats {
+        requests.push(&[("method", method)], stats.requests as f64);
+    }
+    let errors = exposition.family(
+        "dep_rpc_errors_total",
+        MetricKind::Counter,
+        "JSON-RPC requests that failed or reverted, by method.",
+    );
+    for (method, stats) in stats {
+        errors.push(&[("method", method)], stats.errors as f64);
+    }
+    let latency = exposition.family(
+        "dep_rpc_request_duration_seconds",
+        MetricKind::Histogram,
+        "JSON-RPC response time, by method.",
+    );
+    for (method, stats) in stats {
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(stats.buckets) {
+            let bound = bound.to_string();
+            latency.push_suffixed(
+                "_bucket",
+                &[("method", method), ("le", &bound)],
+                count as f64,
+            );
+        }
+        let requests = stats.requests as f64;
+        latency.push_suffixed("_bucket", &[("method", method), ("le", "+Inf")], requests);
+        latency.push_suffixed("_sum", &[("method", method)], stats.seconds);
+        latency.push_suffixed("_count", &[("method", method)], requests);
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsConfig {
+    /// Where `CompleteTask` events start being counted, usually the
+    /// contract's deployment block; `None` counts from the head the first
+    /// refresh sees.
+    pub from_block: Option<u64>,
+    /// Addresses whose EZC balance is exported. Requires the EZC address.
+    pub ezc_holders: Vec<Address>,
+}
+
+/// What a node has earned since `from_block`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NodeTally {
+    pub completions: u64,
+    pub rewards: U256,
+}
+
+/// Reads chain state into metric families, keeping what it can between
+/// scrapes.
+pub struct ChainCollector {
+    client: Client,
+    config: MetricsConfig,
+    watcher: Option<EventWatcher>,
+    nodes: BTreeMap<Address, NodeTally>,
+    final_tasks: BTreeMap<u64, &'static str>,
+}
+
+impl ChainCollector {
+    pub fn new(client: &Client, config: MetricsConfig) -> Result<Self, anyhow::Error> {
+        if !config.ezc_holders.is_empty() && client.ezc_address().is_none() {
+            anyhow::bail!("EZC balances need the network's EZC address");
+        }
+        Ok(ChainCollector {
+            client: client.clone(),
+            watcher: None,
+            config,
+            nodes: BTreeMap::new(),
+            final_tasks: BTreeMap::new(),
+        })
+    }
+
+    pub fn nodes(&self) -> &BTreeMap<Address, NodeTally> {
+        &self.nodes
+    }
+
+    /// Counts `CompleteTask` events up to the head.
+    async fn catch_up(&mut self) -> Result<u64, anyhow::Error> {
+        let head = self.client.block_number().await?;
+        let from_block = self.config.from_block.unwrap_or(head + 1);
+        let client = &self.client;
+        let watcher = self
+            .watcher
+            .get_or_insert_with(|| EventWatcher::new(client, from_block));
+        while watcher.next_block() <= head {
+            for log in watcher.poll().await?.events() {
+                if let DepEvent::CompleteTask(complete) = log.event {
+                    let tally = self.nodes.entry(complete.node).or_default();
+                    tally.completions += 1;
+                    tally.rewards += complete.task_proof;
+                }
+            }
+        }
+        Ok(head)
+    }
+
+    /// Reads the chain state families.
+    pub async fn collect(&mut self) -> Result<Exposition, anyhow::Error> {
+        let head = self.catch_up().await?;
+        let client = self
+            .client
+            .at_block(BlockId::Number(BlockNumber::Number(head.into())));
+        let calls = client.calls();
+        let mut exposition = Exposition::default();
+        exposition.single(
+            "dep_block_number",
+            MetricKind::Gauge,
+            "The block the other chain metrics were read at.",
+            head as f64,
+        );
+
+        let task_sum = calls.task_sum().await?;
+        exposition.single(
+            "dep_task_sum",
+            MetricKind::Gauge,
+            "Tasks published so far (taskSum).",
+            task_sum as f64,
+        );
+        let race_timeout = calls.race_timeout().await?;
+        let complete_timeout = calls.complete_timeout().await?;
+        let now = calls.get_curren_time().await?;
+        let mut counts: BTreeMap<&str, u64> = BTreeMap::new();
+        let mut last_start = None;
+        for task_id in 1..=task_sum {
+            if let Some(label) = self.final_tasks.get(&task_id) {
+                *counts.entry(label).or_default() += 1;
+                continue;
+            }
+            let info = calls.task_info(task_id).await?;
+            if task_id == task_sum {
+                last_start = Some(info.start_time);
+            }
+            let withdrawn = calls.is_withdraw_from_owner(task_id).await?;
+            let status = TaskStatus::derive(&info, race_timeout, complete_timeout, withdrawn, now);
+            let label = status_label(&status);
+            if status.is_final() {
+                self.final_tasks.insert(task_id, label);
+            }
+            *counts.entry(label).or_default() += 1;
+        }
+        if task_sum > 0 && last_start.is_none() {
+            last_start = Some(calls.task_info(task_sum).await?.start_time);
+        }
+        if let Some(start) = last_start {
+            exposition.single(
+                "dep_last_task_start_time_seconds",
+                MetricKind::Gauge,
+                "Unix time the most recent task was published.",
+                start as f64,
+            );
+        }
+        let tasks = exposition.family("dep_tasks", MetricKind::Gauge, "Tasks by lifecycle stage.");
+        for label in STATUS_LABELS {
+            let count = counts.get(label).copied().unwrap_or(0);
+            tasks.push(&[("status", label)], count as f64);
+        }
+
+        let completions = exposition.family(
+            "dep_node_completions_total",
+            MetricKind::Counter,
+            "Runs completed by each node since the first scanned block.",
+        );
+        for (node, tally) in &self.nodes {
+            let node = format!("{:?}", node);
+            completions.push(&[("node", &node)], tally.completions as f64);
+        }
+        let rewards = exposition.family(
+            "dep_node_rewards_ezc_total",
+            MetricKind::Counter,
+            "EZC earned by each node since the first scanned block.",
+        );
+        for (node, tally) in &self.nodes {
+            let node = format!("{:?}", node);
+            rewards.push(&[("node", &node)], ezc(tally.rewards));
+        }
+
+        let day = calls.get_current_day().await?;
+        exposition.single(
+            "dep_day_total_reward_ezc",
+            MetricKind::Gauge,
+            "dayTotalReward of the current day, in EZC.",
+            ezc(calls.day_total_reward(day).await?),
+        );
+
+        if !self.config.ezc_holders.is_empty() {
+            let mut balances = vec![];
+            for holder in &self.config.ezc_holders {
+                balances.push((format!("{:?}", holder), client.ezc_balance(*holder).await?));
+            }
+            let family = exposition.family(
+                "dep_ezc_balance",
+                MetricKind::Gauge,
+                "EZC balance of each configured address.",
+            );
+            for (holder, balance) in &balances {
+                family.push(&[("address", holder)], ezc(*balance));
+            }
+        }
+
+        let parameters = [
+            ("raceTimeout", race_timeout as f64),
+            ("completeTimeout", complete_timeout as f64),
+            ("creditThreshold", calls.credit_threshold().await? as f64),
+            ("blockUintPrice", calls.block_uint_price().await? as f64),
+            ("proofUnit", ezc(calls.proof_unit().await?)),
+            ("initRunNum", calls.init_run_num().await? as f64),
+            ("estimateRunNum", calls.estimate_run_num().await? as f64),
+        ];
+        let family = exposition.family(
+            "dep_contract_parameter",
+            MetricKind::Gauge,
+            "Contract parameters; proofUnit is in EZC.",
+        );
+        for (name, value) in parameters {
+            family.push(&[("name", name)], value);
+        }
+        Ok(exposition)
+    }
+
+    /// Everything a refresh exports. Chain reads that fail are logged and
+    /// reported through `dep_scrape_success`.
+    pub async fn scrape(&mut self) -> Exposition {
+        let start = Instant::now();
+        let (mut exposition, success) = match self.collect().await {
+            Ok(exposition) => (exposition, 1.0),
+            Err(e) => {
+                log::warn!("metrics scrape failed: {}", e);
+                (Exposition::default(), 0.0)
+            }
+        };
+        exposition.single(
+            "dep_scrape_success",
+            MetricKind::Gauge,
+            "Whether the chain state could be read.",
+            success,
+        );
+        exposition.single(
+            "dep_scrape_duration_seconds",
+            MetricKind::Gauge,
+            "Time taken to read the chain state.",
+            start.elapsed().as_secs_f64(),
+        );
+        exposition.single(
+            "dep_scrape_timestamp_seconds",
+            MetricKind::Gauge,
+            "Unix time the chain state was last read.",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        );
+        push_rpc_metrics(&mut exposition, &self.client.rpc_stats().snapshot());
+        exposition
+    }
+}
+
+fn respond(status: StatusCode, text: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(text.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+async fn handle(
+    latest: watch::Receiver<Option<String>>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        return Ok(respond(StatusCode::NOT_FOUND, "not found\n"));
+    }
+    let text = match latest.borrow().clone() {
+        Some(text) => text,
+        None => {
+            return Ok(respond(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "metrics are not collected yet\n",
+            ))
+        }
+    };
+    let mut response = Response::new(Body::from(text));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, CONTENT_TYPE_TEXT.parse().unwrap());
+    Ok(response)
+}
+
+/// Refreshes the collector every `interval` in the background and serves
+/// the last refresh on `addr` until the server fails.
+pub async fn serve(
+    mut collector: ChainCollector,
+    addr: SocketAddr,
+    interval: Duration,
+) -> Result<(), anyhow::Error> {
+    let (sender, latest) = watch::channel(None);
+    tokio::spawn(async move {
+        loop {
+            let text = collector.scrape().await.render();
+            if sender.send(Some(text)).is_err() {
+                return;
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+    let make_service = make_service_fn(move |_| {
+        let latest = latest.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| handle(latest.clone(), request))) }
+    });
+    log::info!("serving metrics on http://{}/metrics", addr);
+    Server::try_bind(&addr)?.serve(make_service).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut exposition = Exposition::default();
+        exposition.single("dep_task_sum", MetricKind::Gauge, "Tasks.", 42.0);
+        let family = exposition.family("dep_tasks", MetricKind::Gauge, "By stage.");
+        family.push(&[("status", "open")], 3.0);
+        family.push(&[("status", "say \"hi\"\n")], 0.5);
+        assert_eq!(
+            exposition.render(),
+            "# HELP dep_task_sum Tasks.\n\
+             # TYPE dep_task_sum gauge\n\
+             dep_task_sum 42\n\
+             # HELP dep_tasks By stage.\n\
+             # TYPE dep_tasks gauge\n\
+             dep_tasks{status=\"open\"} 3\n\
+             dep_tasks{status=\"say \\\"hi\\\"\\n\"} 0.5\n"
+        );
+        assert_eq!(format_value(f64::INFINITY), "+Inf");
+        assert_eq!(format_value(f64::NAN), "NaN");
+    }
+
+    #[test]
+    fn test_rpc_metrics() {
+        let stats = crate::transport::RpcStats::default();
+        stats.record("eth_call", Duration::from_millis(200), true);
+        stats.record("eth_call", Duration::from_secs(30), false);
+        let mut exposition = Exposition::default();
+        push_rpc_metrics(&mut exposition, &stats.snapshot());
+        let text = exposition.render();
+        assert!(text.contains("dep_rpc_requests_total{method=\"eth_call\"} 2\n"));
+        assert!(text.contains("dep_rpc_errors_total{method=\"eth_call\"} 1\n"));
+        assert!(text.contains(
+            "dep_rpc_request_duration_seconds_bucket{method=\"eth_call\",le=\"0.25\"} 1\n"
+        ));
+        assert!(text.contains(
+            "dep_rpc_request_duration_seconds_bucket{method=\"eth_call\",le=\"+Inf\"} 2\n"
+        ));
+        assert!(text.contains("dep_rpc_request_duration_seconds_count{method=\"eth_call\"} 2\n"));
+    }
+
+    #[tokio::test]
+    async fn test_handle() {
+        let get = |path: &str| Request::get(path).body(Body::empty()).unwrap();
+        let (sender, latest) = watch::channel(None);
+        let response = handle(latest.clone(), get("/metrics")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        sender.send(Some("dep_task_sum 42\n".to_string())).unwrap();
+        let response = handle(latest.clone(), get("/metrics")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"dep_task_sum 42\n");
+
+        let response = handle(latest, get("/")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_ezc() {
+        assert_eq!(ezc(U256::exp10(18) * 3 / 2), 1.5);
+        assert_eq!(ezc(U256::zero()), 0.0);
+    }
+}
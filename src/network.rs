@@ -0,0 +1,312 @@
+//! Network profiles for DEP deployments.
+//!
+//! A profile bundles everything needed to talk to one deployment: RPC
+//! endpoints, chain id, contract addresses and the block the DEP contract was
+//! deployed at (the natural starting point for event backfills). Profiles are
+//! loaded from TOML or assembled from environment variables; the one built-in
+//! profile, `mainnet-dev`, only knows its RPC endpoint and contract address.
+//! `Client::connect` fails over between a profile's RPC endpoints and checks
+//! that the one it picks serves the expected chain.
+
+use crate::abi::AbiVersion;
+use crate::account::AccountMapping;
+use crate::Client;
+use ethers::prelude::k256::ecdsa::SigningKey;
+use ethers::prelude::Wallet;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use web3::types::Address;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Network {
+    pub name: String,
+    /// RPC endpoints in order of preference; `Client::connect` uses the
+    /// first one that answers.
+    pub rpc_urls: Vec<String>,
+    /// The EVM chain id, checked by `Client::verify_network` when known.
+    #[serde(default)]
+    pub chain_id: Option<u64>,
+    pub dep_address: Address,
+    #[serde(default)]
+    pub ezc_address: Option<Address>,
+    /// The block the DEP contract was deployed at, when known. Backfills
+    /// without an explicit start block need it.
+    #[serde(default)]
+    pub deployment_block: Option<u64>,
+    /// How the chain maps accounts to EVM addresses, Frontier's defaults
+    /// unless the profile has an `[accounts]` table.
+    #[serde(default)]
+    pub accounts: AccountMapping,
+}
+
+impl Network {
+    /// The Deeper dev chain the DEP contract was first deployed on. Its chain
+    /// id, EZC address and deployment block are unknown and left unset.
+    pub fn mainnet_dev() -> Network {
+        Network {
+            name: "mainnet-dev".to_string(),
+            rpc_urls: vec!["https://mainnet-dev.deeper.network/rpc".to_string()],
+            chain_id: None,
+            dep_address: "0x9397AA12576cEc2A37C60f76d2FB31b31b5E5c7F"
+                .parse()
+                .unwrap(),
+            ezc_address: None,
+            deployment_block: None,
+            accounts: AccountMapping::default(),
+        }
+    }
+
+    pub fn builtins() -> Vec<Network> {
+        vec![Network::mainnet_dev()]
+    }
+
+    pub fn builtin(name: &str) -> Option<Network> {
+        Network::builtins()
+            .into_iter()
+            .find(|network| network.name == name)
+    }
+
+    /// Resolves a built-in profile name or the path of a TOML profile.
+    pub fn load(name_or_path: &str) -> Result<Network, anyhow::Error> {
+        match Network::builtin(name_or_path) {
+            Some(network) => Ok(network),
+            None if Path::new(name_or_path).exists() => Network::from_toml_file(name_or_path),
+            None => anyhow::bail!(
+                "unknown network {:?}, expected one of {:?} or a TOML file",
+                name_or_path,
+                Network::builtins()
+                    .iter()
+                    .map(|network| network.name.as_str())
+                    .collect::<Vec<_>>()
+            ),
+        }
+    }
+
+    pub fn from_toml_str(toml: &str) -> Result<Network, anyhow::Error> {
+        let network: Network = toml::from_str(toml)?;
+        network.validate()?;
+        Ok(network)
+    }
+
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Result<Network, anyhow::Error> {
+        Network::from_toml_str(&fs::read_to_string(path)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String, anyhow::Error> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Builds a profile from `DEP_*` environment variables.
+    ///
+    /// `DEP_NETWORK` selects a built-in profile or TOML file to start from;
+    /// `DEP_RPC_URLS` (comma separated), `DEP_CHAIN_ID`, `DEP_ADDRESS`,
+    /// `DEP_EZC_ADDRESS` and `DEP_DEPLOYMENT_BLOCK` override its fields. Without
+    /// `DEP_NETWORK`, `DEP_RPC_URLS` and `DEP_ADDRESS` are required.
+    pub fn from_env() -> Result<Network, anyhow::Error> {
+        Network::from_vars(|key| std::env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Network, anyhow::Error> {
+        let mut network = match var("DEP_NETWORK") {
+            Some(name) => Network::load(&name)?,
+            None => Network {
+                name: "env".to_string(),
+                rpc_urls: vec![],
+                chain_id: None,
+                dep_address: Address::zero(),
+                ezc_address: None,
+                deployment_block: None,
+                accounts: AccountMapping::default(),
+            },
+        };
+        if let Some(urls) = var("DEP_RPC_URLS") {
+            network.rpc_urls = urls
+                .split(',')
+                .map(|url| url.trim().to_string())
+                .filter(|url| !url.is_empty())
+                .collect();
+        }
+        if let Some(chain_id) = var("DEP_CHAIN_ID") {
+            network.chain_id = Some(chain_id.parse()?);
+        }
+        if let Some(address) = var("DEP_ADDRESS") {
+            network.dep_address = parse_address(&address)?;
+        }
+        if let Some(address) = var("DEP_EZC_ADDRESS") {
+            network.ezc_address = Some(parse_address(&address)?);
+        }
+        if let Some(block) = var("DEP_DEPLOYMENT_BLOCK") {
+            network.deployment_block = Some(block.parse()?);
+        }
+        network.validate()?;
+        Ok(network)
+    }
+
+    /// The preferred RPC endpoint. Panics on a profile without one, which
+    /// `validate` rejects.
+    pub fn rpc_url(&self) -> &str {
+        &self.rpc_urls[0]
+    }
+
+    /// Where a backfill starts: `from_block` when given, otherwise the
+    /// deployment block. Scanning from genesis is never a silent default.
+    pub fn start_block(&self, from_block: Option<u64>) -> Result<u64, anyhow::Error> {
+        from_block.or(self.deployment_block).ok_or_else(|| {
+            anyhow::anyhow!(
+                "network {} has no deployment block, pass a start block",
+                self.name
+            )
+        })
+    }
+
+    /// Checks that the profile names an RPC endpoint and a DEP contract.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.rpc_urls.is_empty() {
+            anyhow::bail!("network {} has no RPC URL", self.name);
+        }
+        if self.dep_address.is_zero() {
+            anyhow::bail!("network {} has no DEP contract address", self.name);
+        }
+        Ok(())
+    }
+}
+
+/// Parses an address with or without the `0x` prefix.
+fn parse_address(address: &str) -> Result<Address, anyhow::Error> {
+    Ok(address.trim().trim_start_matches("0x").parse()?)
+}
+
+impl Client {
+    /// Builds a client for a network profile's first RPC endpoint using the
+    /// latest embedded ABI.
+    pub fn from_network(
+        network: &Network,
+        wallet: Wallet<SigningKey>,
+    ) -> Result<Self, anyhow::Error> {
+        network.validate()?;
+        Client::for_rpc_url(network, network.rpc_url(), wallet)
+    }
+
+    fn for_rpc_url(
+        network: &Network,
+        rpc_url: &str,
+        wallet: Wallet<SigningKey>,
+    ) -> Result<Self, anyhow::Error> {
+        let client = Client::with_abi_version(
+            rpc_url,
+            &format!("{:x}", network.dep_address),
+            AbiVersion::LATEST,
+            wallet,
+        )?;
+        Ok(Client {
+            ezc_address: network.ezc_address,
+            ..client
+        })
+    }
+
+    /// Builds a client for the first of the profile's RPC endpoints that
+    /// answers and serves the expected chain, trying them in order.
+    pub async fn connect(
+        network: &Network,
+        wallet: Wallet<SigningKey>,
+    ) -> Result<Self, anyhow::Error> {
+        network.validate()?;
+        let mut last_error = None;
+        for rpc_url in &network.rpc_urls {
+            let client = Client::for_rpc_url(network, rpc_url, wallet.clone())?;
+            let reachable = match client.eth.block_number().await {
+                Ok(_) => client.verify_network(network).await,
+                Err(e) => Err(e.into()),
+            };
+            match reachable {
+                Ok(()) => return Ok(client),
+                Err(e) => {
+                    log::warn!("skipping RPC endpoint {}: {}", rpc_url, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("no RPC endpoint answered")))
+    }
+
+    /// Checks that the RPC endpoint serves the chain the profile expects.
+    pub async fn verify_network(&self, network: &Network) -> Result<(), anyhow::Error> {
+        if let Some(expected) = network.chain_id {
+            let chain_id = self.eth.chain_id().await?;
+            if chain_id != expected.into() {
+                anyhow::bail!(
+                    "{} expects chain id {} but the RPC endpoint serves {}",
+                    network.name,
+                    expected,
+                    chain_id
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_toml_round_trip() {
+        let network = Network::from_toml_str(
+            r#"
+            name = "staging"
+            rpc_urls = ["http://localhost:9933"]
+            chain_id = 518
+            dep_address = "0x9397aa12576cec2a37c60f76d2fb31b31b5e5c7f"
+            deployment_block = 1200
+
+            [accounts]
+            ss58_prefix = 7
+            "#,
+        )
+        .unwrap();
+        assert_eq!(network.chain_id, Some(518));
+        assert_eq!(network.accounts.ss58_prefix, 7);
+        assert_eq!(network.accounts.hashed_address_prefix, "evm:");
+        assert_eq!(network.ezc_address, None);
+        assert_eq!(network.dep_address, Network::mainnet_dev().dep_address);
+        assert_eq!(
+            Network::from_toml_str(&network.to_toml().unwrap()).unwrap(),
+            network
+        );
+    }
+
+    #[test]
+    fn test_env_overrides_builtin() {
+        let vars: HashMap<&str, &str> = [
+            ("DEP_NETWORK", "mainnet-dev"),
+            ("DEP_RPC_URLS", "http://a:9933, http://b:9933"),
+            ("DEP_DEPLOYMENT_BLOCK", "42"),
+        ]
+        .into_iter()
+        .collect();
+        let network = Network::from_vars(|key| vars.get(key).map(|v| v.to_string())).unwrap();
+        assert_eq!(network.name, "mainnet-dev");
+        assert_eq!(network.rpc_urls, vec!["http://a:9933", "http://b:9933"]);
+        assert_eq!(network.deployment_block, Some(42));
+        assert_eq!(network.start_block(Some(7)).unwrap(), 7);
+        assert_eq!(network.start_block(None).unwrap(), 42);
+        assert!(Network::mainnet_dev().start_block(None).is_err());
+        assert!(Network::from_vars(|_| None).is_err());
+    }
+
+    #[test]
+    fn test_client_requires_rpc_url() {
+        let wallet: ethers::signers::LocalWallet =
+            "0000000000000000000000000000000000000000000000000000000000000001"
+                .parse()
+                .unwrap();
+        let network = Network {
+            rpc_urls: vec![],
+            ..Network::mainnet_dev()
+        };
+        assert!(Client::from_network(&network, wallet).is_err());
+    }
+}
@@ -0,0 +1,428 @@
+//! Typed form of the task `options` string.
+//!
+//! New tasks should be published with `TaskOptions`, which serializes to a
+//! strict JSON object. Options of historical `TaskPublished` events were
+//! written by hand in several shapes, so `TaskOptions::decode_lenient`
+//! accepts whatever it can make sense of instead of failing.
+
+use crate::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+use web3::types::{Address, TransactionReceipt};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TaskOptions {
+    pub env: BTreeMap<String, String>,
+    pub ports: Vec<PortMapping>,
+    pub resources: ResourceLimits,
+    pub args: Vec<String>,
+    pub restart: RestartPolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortMapping {
+    pub host: u16,
+    pub container: u16,
+    #[serde(default)]
+    pub protocol: Protocol,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResourceLimits {
+    /// Fractional CPU cores.
+    pub cpus: Option<f64>,
+    pub memory_mb: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "policy", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure {
+        max_retries: u32,
+    },
+    Always,
+}
+
+impl TaskOptions {
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if let Some(key) = self.env.keys().find(|key| !valid_env_name(key)) {
+            anyhow::bail!("invalid environment variable name {:?}", key);
+        }
+        let mut host_ports = HashSet::new();
+        for port in &self.ports {
+            if port.host == 0 || port.container == 0 {
+                anyhow::bail!("port 0 is not a valid mapping");
+            }
+            if !host_ports.insert((port.host, port.protocol)) {
+                anyhow::bail!("host port {} is mapped twice", port.host);
+            }
+        }
+        if let Some(cpus) = self.resources.cpus.filter(|cpus| !valid_cpus(*cpus)) {
+            anyhow::bail!("cpus must be positive, got {}", cpus);
+        }
+        if self.resources.memory_mb == Some(0) {
+            anyhow::bail!("memory_mb must be positive");
+        }
+        Ok(())
+    }
+
+    /// Serializes validated options into the string stored on chain.
+    pub fn to_json(&self) -> Result<String, anyhow::Error> {
+        self.validate()?;
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Strictly parses options written by `to_json`.
+    pub fn from_json(json: &str) -> Result<Self, anyhow::Error> {
+        let options: TaskOptions = serde_json::from_str(json)?;
+        options.validate()?;
+        Ok(options)
+    }
+
+    /// Best-effort decoding of historical `TaskPublished.options` values.
+    ///
+    /// Accepts the strict format, loosely shaped JSON objects (aliased keys,
+    /// `"K=V"` env lists, `"8080:80"` ports, `"512m"` memory) and plain
+    /// strings of `K=V` pairs and arguments. Anything that cannot be
+    /// interpreted or that `validate` would reject is dropped with a warning,
+    /// so the result is always valid.
+    pub fn decode_lenient(raw: &str) -> Self {
+        let raw = raw.trim();
+        if raw.is_empty() {
+            return TaskOptions::default();
+        }
+        if let Ok(options) = TaskOptions::from_json(raw) {
+            return options;
+        }
+        let mut options = TaskOptions::decode_fields(raw);
+        options.drop_invalid();
+        options
+    }
+
+    fn decode_fields(raw: &str) -> Self {
+        match serde_json::from_str::<Value>(raw) {
+            Ok(Value::Object(fields)) => {
+                let mut options = TaskOptions::default();
+                for (key, value) in fields {
+                    options.apply_lenient(&key, value);
+                }
+                options
+            }
+            _ => {
+                let mut options = TaskOptions::default();
+                for word in raw.split_whitespace() {
+                    match word.split_once('=') {
+                        Some((key, value)) => {
+                            options.env.insert(key.to_string(), value.to_string());
+                        }
+                        None => options.args.push(word.to_string()),
+                    }
+                }
+                options
+            }
+        }
+    }
+
+    /// Drops every field `validate` rejects.
+    fn drop_invalid(&mut self) {
+        self.env.retain(|key, _| {
+            let valid = valid_env_name(key);
+            if !valid {
+                log::warn!("ignoring invalid environment variable name {:?}", key);
+            }
+            valid
+        });
+        let mut host_ports = HashSet::new();
+        self.ports.retain(|port| {
+            let valid = port.host != 0
+                && port.container != 0
+                && host_ports.insert((port.host, port.protocol));
+            if !valid {
+                log::warn!("ignoring invalid or duplicate task port {:?}", port);
+            }
+            valid
+        });
+        if let Some(cpus) = self.resources.cpus.filter(|cpus| !valid_cpus(*cpus)) {
+            log::warn!("ignoring task cpus {}", cpus);
+            self.resources.cpus = None;
+        }
+        if self.resources.memory_mb == Some(0) {
+            log::warn!("ignoring task memory below 1 MB");
+            self.resources.memory_mb = None;
+        }
+    }
+
+    fn apply_lenient(&mut self, key: &str, value: Value) {
+        match key.to_ascii_lowercase().as_str() {
+            "env" | "envs" | "environment" => match value {
+                Value::Object(vars) => {
+                    for (name, value) in vars {
+                        self.env.insert(name, plain_string(value));
+                    }
+                }
+                Value::Array(vars) => {
+                    for var in vars {
+                        if let Some((name, value)) = plain_string(var).split_once('=') {
+                            self.env.insert(name.to_string(), value.to_string());
+                        }
+                    }
+                }
+                other => log::warn!("ignoring task env {}", other),
+            },
+            "port" | "ports" => {
+                let ports = match value {
+                    Value::Array(ports) => ports,
+                    other => vec![other],
+                };
+                for port in ports {
+                    match lenient_port(&port) {
+                        Some(port) => self.ports.push(port),
+                        None => log::warn!("ignoring task port {}", port),
+                    }
+                }
+            }
+            "cpu" | "cpus" => {
+                let text = plain_string(value);
+                self.resources.cpus = text.parse().ok();
+                if self.resources.cpus.is_none() {
+                    log::warn!("ignoring task cpus {:?}", text);
+                }
+            }
+            "memory" | "mem" | "memory_mb" => {
+                let text = plain_string(value);
+                self.resources.memory_mb = lenient_memory_mb(&text);
+                if self.resources.memory_mb.is_none() {
+                    log::warn!("ignoring task memory {:?}", text);
+                }
+            }
+            "resources" => {
+                if let Value::Object(limits) = value {
+                    for (key, value) in limits {
+                        self.apply_lenient(&key, value);
+                    }
+                }
+            }
+            "args" | "arg" | "cmd" | "command" => match value {
+                Value::Array(args) => self.args.extend(args.into_iter().map(plain_string)),
+                other => self
+                    .args
+                    .extend(plain_string(other).split_whitespace().map(String::from)),
+            },
+            "restart" | "restart_policy" => {
+                self.restart = match plain_string(value).as_str() {
+                    "always" | "unless-stopped" => RestartPolicy::Always,
+                    "on-failure" | "on_failure" => RestartPolicy::OnFailure { max_retries: 3 },
+                    _ => RestartPolicy::Never,
+                }
+            }
+            other => log::warn!("ignoring unknown task option {:?}", other),
+        }
+    }
+}
+
+impl FromStr for TaskOptions {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TaskOptions::from_json(s)
+    }
+}
+
+fn valid_env_name(key: &str) -> bool {
+    key.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn valid_cpus(cpus: f64) -> bool {
+    cpus.is_finite() && cpus > 0.0
+}
+
+/// A JSON value as text, without the quotes around strings.
+fn plain_string(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+/// Parses `8080`, `"8080"`, `"8080:80"`, `"8080:80/udp"` or a strict
+/// mapping object.
+fn lenient_port(value: &Value) -> Option<PortMapping> {
+    if let Ok(port) = serde_json::from_value::<PortMapping>(value.clone()) {
+        return Some(port);
+    }
+    let text = plain_string(value.clone());
+    let (ports, protocol) = match text.split_once('/') {
+        Some((ports, "udp")) => (ports, Protocol::Udp),
+        Some((ports, _)) => (ports, Protocol::Tcp),
+        None => (text.as_str(), Protocol::Tcp),
+    };
+    let (host, container) = match ports.split_once(':') {
+        Some((host, container)) => (host.parse().ok()?, container.parse().ok()?),
+        None => {
+            let port = ports.parse().ok()?;
+            (port, port)
+        }
+    };
+    Some(PortMapping {
+        host,
+        container,
+        protocol,
+    })
+}
+
+/// Parses `512`, `"512m"`, `"512MB"` or `"2g"` into megabytes.
+fn lenient_memory_mb(text: &str) -> Option<u64> {
+    let text = text.trim().to_ascii_lowercase();
+    let digits = text.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let value: u64 = digits.parse().ok()?;
+    match &text[digits.len()..] {
+        "" | "m" | "mb" | "mi" | "mib" => Some(value),
+        "g" | "gb" | "gi" | "gib" => value.checked_mul(1024),
+        "k" | "kb" | "ki" | "kib" => Some(value / 1024),
+        _ => None,
+    }
+}
+
+impl Client {
+    /// Publishes a task for any node with validated, typed options.
+    pub async fn n_node_unspecified_address_task_with_options(
+        &self,
+        url: &str,
+        options: &TaskOptions,
+        max_run_num: u64,
+        maintain_blocks: u64,
+    ) -> Result<TransactionReceipt, anyhow::Error> {
+        self.n_node_unspecified_address_task(url, &options.to_json()?, max_run_num, maintain_blocks)
+            .await
+    }
+
+    /// Publishes a task for the given receivers with validated, typed options.
+    pub async fn n_nodespecified_address_task_with_options(
+        &self,
+        url: &str,
+        options: &TaskOptions,
+        max_run_num: u64,
+        receivers: Vec<Address>,
+        maintain_blocks: u64,
+    ) -> Result<TransactionReceipt, anyhow::Error> {
+        self.n_nodespecified_address_task(
+            url,
+            &options.to_json()?,
+            max_run_num,
+            receivers,
+            maintain_blocks,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut options = TaskOptions {
+            ports: vec![PortMapping {
+                host: 8080,
+                container: 80,
+                protocol: Protocol::Tcp,
+            }],
+            resources: ResourceLimits {
+                cpus: Some(0.5),
+                memory_mb: Some(256),
+            },
+            args: vec!["--interval".to_string(), "60".to_string()],
+            restart: RestartPolicy::OnFailure { max_retries: 2 },
+            ..TaskOptions::default()
+        };
+        options
+            .env
+            .insert("PAIR".to_string(), "DPR/USDT".to_string());
+        let json = options.to_json().unwrap();
+        assert_eq!(json.parse::<TaskOptions>().unwrap(), options);
+        assert!(TaskOptions::from_json(r#"{"unknown": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut options = TaskOptions::default();
+        options.env.insert("1BAD".to_string(), String::new());
+        assert!(options.validate().is_err());
+
+        let port = PortMapping {
+            host: 8080,
+            container: 80,
+            protocol: Protocol::Tcp,
+        };
+        let options = TaskOptions {
+            ports: vec![port, port],
+            ..TaskOptions::default()
+        };
+        assert!(options.to_json().is_err());
+    }
+
+    #[test]
+    fn test_decode_lenient() {
+        assert_eq!(TaskOptions::decode_lenient(""), TaskOptions::default());
+
+        let options = TaskOptions::decode_lenient(
+            r#"{"environment": ["PAIR=DPR/USDT"], "ports": ["8080:80/udp", 9000],
+                "mem": "1g", "cmd": "run --once", "restart": "always", "color": "red"}"#,
+        );
+        assert_eq!(options.env["PAIR"], "DPR/USDT");
+        assert_eq!(
+            options.ports,
+            vec![
+                PortMapping {
+                    host: 8080,
+                    container: 80,
+                    protocol: Protocol::Udp
+                },
+                PortMapping {
+                    host: 9000,
+                    container: 9000,
+                    protocol: Protocol::Tcp
+                },
+            ]
+        );
+        assert_eq!(options.resources.memory_mb, Some(1024));
+        assert_eq!(options.args, vec!["run", "--once"]);
+        assert_eq!(options.restart, RestartPolicy::Always);
+
+        let options = TaskOptions::decode_lenient(r#"{"memory": "18014398509481984g"}"#);
+        assert_eq!(options.resources.memory_mb, None);
+
+        let options = TaskOptions::decode_lenient(
+            r#"{"env": {"1BAD": "x", "GOOD": "y"}, "ports": [0, 80, "80:8080"],
+                "memory": "512k", "cpus": -1}"#,
+        );
+        assert_eq!(options.env.keys().collect::<Vec<_>>(), vec!["GOOD"]);
+        assert_eq!(options.ports.len(), 1);
+        assert_eq!(options.resources, ResourceLimits::default());
+        assert!(options.validate().is_ok());
+        assert_eq!(TaskOptions::decode_lenient("1BAD=x").env.len(), 0);
+
+        let options = TaskOptions::decode_lenient("PAIR=DPR/USDT --verbose");
+        assert_eq!(options.env["PAIR"], "DPR/USDT");
+        assert_eq!(options.args, vec!["--verbose"]);
+    }
+}
@@ -0,0 +1,295 @@
+//! Checked task publishing.
+//!
+//! `TaskBuilder` validates a task against the contract's preconditions before
+//! sending it, so that a mistake costs an error instead of a reverted (or,
+//! for `maxRunNum == 0`, a permanently broken) task, and reads the new task
+//! id back from the `TaskPublished` log.
+
+use crate::events::{DepEvent, TaskPublished};
+use crate::options::TaskOptions;
+use crate::Client;
+use std::collections::HashSet;
+use web3::ethabi::{self, Token};
+use web3::types::{Address, Bytes, CallRequest, TransactionReceipt, U256};
+
+/// The ERC20 `balanceOf(address)` selector.
+const BALANCE_OF: [u8; 4] = [0x70, 0xa0, 0x82, 0x31];
+
+/// What the contract burns to publish a task, mirroring its `initTask`
+/// modifier. Returns `None` when the contract would divide by zero or
+/// overflow.
+pub fn task_cost(
+    proof_unit: U256,
+    block_uint_price: u64,
+    max_runs: u64,
+    maintain_blocks: u64,
+) -> Option<U256> {
+    let block_price = if maintain_blocks > 100 {
+        maintain_blocks.checked_div(block_uint_price)?
+    } else {
+        1
+    };
+    proof_unit
+        .checked_mul(max_runs.into())?
+        .checked_mul(block_price.into())
+}
+
+#[derive(Debug, Clone)]
+pub struct PublishedTask {
+    pub task_id: u64,
+    /// The EZC burned for the task.
+    pub cost: U256,
+    pub receipt: TransactionReceipt,
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskBuilder {
+    client: Client,
+    image: Option<String>,
+    options: TaskOptions,
+    max_runs: u64,
+    receivers: Vec<Address>,
+    maintain_blocks: u64,
+    check_balance: bool,
+    publisher: Option<Address>,
+}
+
+impl TaskBuilder {
+    /// Starts a task that runs once, on any node, without a maintained
+    /// lifetime.
+    pub fn new(client: &Client) -> Self {
+        TaskBuilder {
+            client: client.clone(),
+            image: None,
+            options: TaskOptions::default(),
+            max_runs: 1,
+            receivers: vec![],
+            maintain_blocks: 0,
+            check_balance: true,
+            publisher: None,
+        }
+    }
+
+    pub fn image(mut self, url: &str) -> Self {
+        self.image = Some(url.to_string());
+        self
+    }
+
+    pub fn options(mut self, options: TaskOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn max_runs(mut self, max_runs: u64) -> Self {
+        self.max_runs = max_runs;
+        self
+    }
+
+    /// Restricts the task to these nodes; an empty list accepts any node.
+    /// Takes addresses or `NodeAddress`es, which may be parsed from SS58.
+    pub fn receivers(mut self, receivers: impl IntoIterator<Item = impl Into<Address>>) -> Self {
+        self.receivers = receivers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn maintain_blocks(mut self, maintain_blocks: u64) -> Self {
+        self.maintain_blocks = maintain_blocks;
+        self
+    }
+
+    /// Publishes without checking the EZC balance, for clients that do not
+    /// know the EZC address. The contract still refuses an underfunded task.
+    pub fn skip_balance_check(mut self) -> Self {
+        self.check_balance = false;
+        self
+    }
+
+    /// Validates on behalf of `publisher` instead of the client's wallet, so
+    /// a dry run can check an operator's whitelisting and balance without
+    /// their key. `publish` refuses a builder with another publisher.
+    pub fn publisher(mut self, publisher: Address) -> Self {
+        self.publisher = Some(publisher);
+        self
+    }
+
+    /// Checks everything that can be checked without sending a transaction
+    /// and returns the EZC the task will burn.
+    pub async fn validate(&self) -> Result<U256, anyhow::Error> {
+        if self.image.as_deref().is_none_or(str::is_empty) {
+            anyhow::bail!("task has no image");
+        }
+        if self.max_runs == 0 {
+            anyhow::bail!("max_runs must be positive");
+        }
+        let mut seen = HashSet::new();
+        if let Some(duplicate) = self.receivers.iter().find(|r| !seen.insert(*r)) {
+            anyhow::bail!("receiver {:?} is listed twice", duplicate);
+        }
+        self.options.validate()?;
+
+        let publisher = self.publisher.unwrap_or_else(|| self.client.address());
+        let (whitelisted,) = self.client.address_whitelist(publisher).await?;
+        if !whitelisted {
+            anyhow::bail!("{:?} is not in the address whitelist", publisher);
+        }
+        let cost = self
+            .client
+            .estimate_task_cost(self.max_runs, self.maintain_blocks)
+            .await?;
+        if !self.check_balance {
+            return Ok(cost);
+        }
+        if self.client.ezc_address().is_none() {
+            anyhow::bail!("EZC address unknown, cannot check the publisher's balance");
+        }
+        let balance = self.client.ezc_balance(publisher).await?;
+        if balance < cost {
+            anyhow::bail!(
+                "publishing costs {} EZC but {:?} holds {}",
+                cost,
+                publisher,
+                balance
+            );
+        }
+        Ok(cost)
+    }
+
+    /// Validates and publishes the task.
+    pub async fn publish(self) -> Result<PublishedTask, anyhow::Error> {
+        if let Some(publisher) = self.publisher.filter(|p| *p != self.client.address()) {
+            anyhow::bail!("the wallet cannot publish on behalf of {:?}", publisher);
+        }
+        let cost = self.validate().await?;
+        let url = self.image.as_deref().unwrap_or_default();
+        let receipt = if self.receivers.is_empty() {
+            self.client
+                .n_node_unspecified_address_task_with_options(
+                    url,
+                    &self.options,
+                    self.max_runs,
+                    self.maintain_blocks,
+                )
+                .await?
+        } else {
+            self.client
+                .n_nodespecified_address_task_with_options(
+                    url,
+                    &self.options,
+                    self.max_runs,
+                    self.receivers.clone(),
+                    self.maintain_blocks,
+                )
+                .await?
+        };
+        if receipt.status != Some(1.into()) {
+            anyhow::bail!("publishing reverted in {:?}", receipt.transaction_hash);
+        }
+        let published = published_task(&receipt, self.client.contract.address())?;
+        Ok(PublishedTask {
+            task_id: published.task_id,
+            cost,
+            receipt,
+        })
+    }
+}
+
+/// Finds the `TaskPublished` event `contract` emitted in a publish receipt,
+/// skipping logs of other contracts and logs that do not decode.
+fn published_task(
+    receipt: &TransactionReceipt,
+    contract: Address,
+) -> Result<TaskPublished, anyhow::Error> {
+    for log in receipt.logs.iter().filter(|log| log.address == contract) {
+        if let Ok(Some(DepEvent::TaskPublished(published))) = DepEvent::decode(log) {
+            return Ok(published);
+        }
+    }
+    anyhow::bail!("no TaskPublished log in {:?}", receipt.transaction_hash)
+}
+
+impl Client {
+    /// Starts building a task to publish from this client's wallet.
+    pub fn task_builder(&self) -> TaskBuilder {
+        TaskBuilder::new(self)
+    }
+
+    /// The EZC a task with these parameters would burn at current prices.
+    pub async fn estimate_task_cost(
+        &self,
+        max_runs: u64,
+        maintain_blocks: u64,
+    ) -> Result<U256, anyhow::Error> {
+        let (proof_unit,) = self.proof_unit().await?;
+        let (block_unit_price,) = self.block_unit_price().await?;
+        task_cost(proof_unit, block_unit_price, max_runs, maintain_blocks)
+            .ok_or_else(|| anyhow::anyhow!("blockUintPrice is zero or the cost overflows"))
+    }
+
+    /// The EZC balance of `account`. Requires the EZC address from the
+    /// network profile or `with_ezc_address`.
+    pub async fn ezc_balance(&self, account: Address) -> Result<U256, anyhow::Error> {
+        let ezc = self
+            .ezc_address
+            .ok_or_else(|| anyhow::anyhow!("EZC address is not configured"))?;
+        let mut data = BALANCE_OF.to_vec();
+        data.extend(ethabi::encode(&[Token::Address(account)]));
+        let output = self
+            .eth
+            .call(
+                CallRequest {
+                    to: Some(ezc),
+                    data: Some(Bytes(data)),
+                    ..CallRequest::default()
+                },
+                self.block,
+            )
+            .await?;
+        match ethabi::decode(&[ethabi::ParamType::Uint(256)], &output.0)?.pop() {
+            Some(Token::Uint(balance)) => Ok(balance),
+            _ => anyhow::bail!("unexpected balanceOf output"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::events::raw_log;
+
+    #[test]
+    fn test_task_cost() {
+        let unit = U256::exp10(18);
+        assert_eq!(task_cost(unit, 10, 3, 0), Some(unit * 3));
+        assert_eq!(task_cost(unit, 10, 3, 100), Some(unit * 3));
+        assert_eq!(task_cost(unit, 10, 3, 600), Some(unit * 180));
+        assert_eq!(task_cost(unit, 10, 3, 105), Some(unit * 30));
+        assert_eq!(task_cost(unit, 0, 3, 600), None);
+        assert_eq!(task_cost(U256::MAX, 10, 3, 0), None);
+    }
+
+    #[test]
+    fn test_published_task_from_receipt() {
+        let data = ethabi::encode(&[
+            Token::Uint(7.into()),
+            Token::String("xbgxwh/oracle_price:1.0.3".to_string()),
+            Token::String(String::new()),
+            Token::Uint(2.into()),
+            Token::Array(vec![]),
+            Token::Uint(600.into()),
+        ]);
+        let contract = Address::zero();
+        let mut other = raw_log(9, vec![TaskPublished::SIGNATURE], data.clone());
+        other.address = Address::repeat_byte(1);
+        let receipt = TransactionReceipt {
+            logs: vec![
+                other,
+                raw_log(9, vec![TaskPublished::SIGNATURE], vec![0; 3]),
+                raw_log(9, vec![TaskPublished::SIGNATURE], data),
+            ],
+            ..TransactionReceipt::default()
+        };
+        assert_eq!(published_task(&receipt, contract).unwrap().task_id, 7);
+        assert!(published_task(&receipt, Address::repeat_byte(2)).is_err());
+        assert!(published_task(&TransactionReceipt::default(), contract).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//! Daily reconciliation of node earnings against the reward pool.
+//!
+//! `dayTotalReward[day]` grows by each task's `taskProof` when it is
+//! published, while `userDayReward[node][day]` grows by `taskUintProof` when
+//! a node completes a run. The two only match when every run of every task
+//! published that day is completed that same day. The report lists who
+//! earned what and explains the gap with the day's tasks whose runs were
+//! never completed; what is left over comes from completions of tasks
+//! published on other days and from `increaseTaskDuration` top-ups.
+
+use crate::day::DepDay;
+use crate::events::DepEvent;
+use crate::rewards::{format_ezc, ratio};
+use crate::Client;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use web3::types::{Address, U256};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeEarning {
+    pub node: Address,
+    /// `CompleteTask` events the node emitted that day.
+    pub completions: u64,
+    pub earned: U256,
+    /// The node's fraction of all earnings that day.
+    pub share: f64,
+}
+
+/// A task published that day with runs that were never completed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UncompletedTask {
+    pub task_id: u64,
+    pub publisher: Address,
+    pub max_runs: u64,
+    pub completed_runs: u64,
+    pub task_proof: U256,
+    /// `taskProof` not paid out to any node.
+    pub uncompleted: U256,
+}
+
+/// The difference between two amounts, keeping its direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Discrepancy {
+    Balanced,
+    /// Less was paid out than expected.
+    Short(U256),
+    /// More was paid out than expected.
+    Over(U256),
+}
+
+impl Discrepancy {
+    fn between(expected: U256, actual: U256) -> Self {
+        if expected > actual {
+            Discrepancy::Short(expected - actual)
+        } else if actual > expected {
+            Discrepancy::Over(actual - expected)
+        } else {
+            Discrepancy::Balanced
+        }
+    }
+
+    /// The discrepancy in EZC, negative when more was paid out than expected.
+    pub fn to_ezc_string(&self) -> String {
+        match self {
+            Discrepancy::Balanced => "0".to_string(),
+            Discrepancy::Short(amount) => format_ezc(*amount),
+            Discrepancy::Over(amount) => format!("-{}", format_ezc(*amount)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconciliationReport {
+    pub day: DepDay,
+    pub from_block: u64,
+    pub to_block: u64,
+    /// `getTotalRewardForDay(day)`.
+    pub pool: U256,
+    pub nodes: Vec<NodeEarning>,
+    pub uncompleted: Vec<UncompletedTask>,
+}
+
+impl ReconciliationReport {
+    /// Everything the nodes earned that day.
+    pub fn earned(&self) -> U256 {
+        self.nodes
+            .iter()
+            .fold(U256::zero(), |total, node| total + node.earned)
+    }
+
+    pub fn uncompleted_total(&self) -> U256 {
+        self.uncompleted
+            .iter()
+            .fold(U256::zero(), |total, task| total + task.uncompleted)
+    }
+
+    /// The pool minus what the nodes earned.
+    pub fn discrepancy(&self) -> Discrepancy {
+        Discrepancy::between(self.pool, self.earned())
+    }
+
+    /// The discrepancy not explained by the day's uncompleted tasks.
+    pub fn unexplained(&self) -> Discrepancy {
+        Discrepancy::between(self.pool, self.earned() + self.uncompleted_total())
+    }
+
+    /// One row per node and uncompleted task, followed by the totals.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("kind,task_id,address,amount_ezc,share,completions\n");
+        for node in &self.nodes {
+            let _ = writeln!(
+                csv,
+                "node,,{:?},{},{:.6},{}",
+                node.node,
+                format_ezc(node.earned),
+                node.share,
+                node.completions
+            );
+        }
+        for task in &self.uncompleted {
+            let _ = writeln!(
+                csv,
+                "uncompleted_task,{},{:?},{},,",
+                task.task_id,
+                task.publisher,
+                format_ezc(task.uncompleted)
+            );
+        }
+        for (kind, amount) in [
+            ("pool", format_ezc(self.pool)),
+            ("earned", format_ezc(self.earned())),
+            ("discrepancy", self.discrepancy().to_ezc_string()),
+            ("uncompleted", format_ezc(self.uncompleted_total())),
+            ("unexplained", self.unexplained().to_ezc_string()),
+        ] {
+            let _ = writeln!(csv, "{},,,{},,", kind, amount);
+        }
+        csv
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "day": self.day,
+            "date": self.day.to_string(),
+            "from_block": self.from_block,
+            "to_block": self.to_block,
+            "pool": format_ezc(self.pool),
+            "earned": format_ezc(self.earned()),
+            "discrepancy": self.discrepancy().to_ezc_string(),
+            "uncompleted_total": format_ezc(self.uncompleted_total()),
+            "unexplained": self.unexplained().to_ezc_string(),
+            "nodes": self.nodes.iter().map(|node| json!({
+                "node": node.node,
+                "completions": node.completions,
+                "earned": format_ezc(node.earned),
+                "share": node.share,
+            })).collect::<Vec<_>>(),
+            "uncompleted": self.uncompleted.iter().map(|task| json!({
+                "task_id": task.task_id,
+                "publisher": task.publisher,
+                "max_runs": task.max_runs,
+                "completed_runs": task.completed_runs,
+                "task_proof": format_ezc(task.task_proof),
+                "uncompleted": format_ezc(task.uncompleted),
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl Client {
+    /// Reconciles the earnings of every node that completed a run on `day`
+    /// against that day's pool.
+    pub async fn reconcile_day(&self, day: DepDay) -> Result<ReconciliationReport, anyhow::Error> {
+        let (from_block, to_block) = self
+            .blocks_of_day(day)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("day {} has no blocks yet", day))?;
+
+        let mut completions: BTreeMap<Address, u64> = BTreeMap::new();
+        let mut published = BTreeSet::new();
+        for log in self.events_between(from_block, to_block).await? {
+            match log.event {
+                DepEvent::CompleteTask(complete) => {
+                    *completions.entry(complete.node).or_default() += 1
+                }
+                DepEvent::TaskPublished(task) => {
+                    published.insert(task.task_id);
+                }
+                _ => {}
+            }
+        }
+
+        let client = self.snapshot().await?;
+        let calls = client.calls();
+        let mut nodes = vec![];
+        for (node, completions) in completions {
+            nodes.push(NodeEarning {
+                node,
+                completions,
+                earned: calls.user_day_reward(node, day.0).await?,
+                share: 0.0,
+            });
+        }
+        let earned = nodes
+            .iter()
+            .fold(U256::zero(), |total, node| total + node.earned);
+        for node in &mut nodes {
+            if !earned.is_zero() {
+                node.share = ratio(node.earned, earned);
+            }
+        }
+
+        let mut uncompleted = vec![];
+        for task_id in published {
+            let info = calls.task_info(task_id).await?;
+            let completed_runs = info.current_run_num - info.current_running_num;
+            let paid = info.task_uint_proof * completed_runs;
+            if info.task_proof > paid {
+                uncompleted.push(UncompletedTask {
+                    task_id,
+                    publisher: info.publisher,
+                    max_runs: info.max_run_num,
+                    completed_runs,
+                    task_proof: info.task_proof,
+                    uncompleted: info.task_proof - paid,
+                });
+            }
+        }
+
+        Ok(ReconciliationReport {
+            day,
+            from_block,
+            to_block,
+            pool: calls.get_total_reward_for_day(day.0).await?,
+            nodes,
+            uncompleted,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn report() -> ReconciliationReport {
+        let ezc = U256::exp10(18);
+        ReconciliationReport {
+            day: DepDay(19219),
+            from_block: 100,
+            to_block: 200,
+            pool: ezc * 10,
+            nodes: vec![NodeEarning {
+                node: Address::from_low_u64_be(1),
+                completions: 2,
+                earned: ezc * 6,
+                share: 1.0,
+            }],
+            uncompleted: vec![UncompletedTask {
+                task_id: 4,
+                publisher: Address::from_low_u64_be(2),
+                max_runs: 2,
+                completed_runs: 1,
+                task_proof: ezc * 6,
+                uncompleted: ezc * 3,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_discrepancy_explained() {
+        let report = report();
+        assert_eq!(
+            report.discrepancy(),
+            Discrepancy::Short(U256::exp10(18) * 4)
+        );
+        assert_eq!(report.unexplained(), Discrepancy::Short(U256::exp10(18)));
+        assert_eq!(Discrepancy::Over(U256::exp10(17)).to_ezc_string(), "-0.1");
+    }
+
+    #[test]
+    fn test_exports() {
+        let report = report();
+        let csv = report.to_csv();
+        assert!(csv.starts_with("kind,task_id,address,amount_ezc,share,completions\n"));
+        assert!(csv.contains("node,,0x0000000000000000000000000000000000000001,6,1.000000,2\n"));
+        assert!(csv.contains("uncompleted_task,4,0x0000000000000000000000000000000000000002,3,,\n"));
+        assert!(csv.contains("unexplained,,,1,,\n"));
+        let json = report.to_json();
+        assert_eq!(json["discrepancy"], "4");
+        assert_eq!(json["nodes"][0]["earned"], "6");
+    }
+}
@@ -0,0 +1,232 @@
+//! Owner-side sweeper for publisher refunds.
+//!
+//! Once a task's `completeTimeout` has passed, the EZC of its unused runs can
+//! be minted back to the publisher, but only by the owner and only one
+//! `withdrawEZC` call per task. The sweeper finds every task in that state
+//! and withdraws them one after another, which keeps the owner's nonces in
+//! order.
+
+use crate::bindings::TaskInfoOutput;
+use crate::status::TaskStatus;
+use crate::Client;
+use std::collections::BTreeMap;
+use web3::types::{Address, H256, U256};
+
+/// A refund `withdrawEZC` would mint for a task.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refund {
+    pub task_id: u64,
+    pub publisher: Address,
+    pub amount: U256,
+}
+
+/// Refundable tasks found by a scan.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefundScan {
+    pub refunds: Vec<Refund>,
+    /// The lowest scanned task id that may still become refundable later.
+    /// Every task below it is either final or in `refunds`, so once those
+    /// are withdrawn the next scan can start here.
+    pub resume_from: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RefundReport {
+    /// Refunds minted, with their transaction hashes.
+    pub refunded: Vec<(Refund, H256)>,
+    /// Refunds whose withdrawal failed or reverted, with the reason.
+    pub failed: Vec<(Refund, String)>,
+    pub resume_from: u64,
+}
+
+/// Refunds minted to one publisher.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PublisherRefunds {
+    pub task_ids: Vec<u64>,
+    pub total: U256,
+}
+
+impl RefundReport {
+    pub fn by_publisher(&self) -> BTreeMap<Address, PublisherRefunds> {
+        let mut publishers: BTreeMap<Address, PublisherRefunds> = BTreeMap::new();
+        for (refund, _) in &self.refunded {
+            let entry = publishers.entry(refund.publisher).or_default();
+            entry.task_ids.push(refund.task_id);
+            entry.total += refund.amount;
+        }
+        publishers
+    }
+
+    pub fn total(&self) -> U256 {
+        self.refunded
+            .iter()
+            .fold(U256::zero(), |total, (refund, _)| total + refund.amount)
+    }
+}
+
+/// The EZC `withdrawEZC` would mint for a task at contract time `now`, or
+/// `None` while it would revert. Applies the contract's checks:
+/// `startTime + completeTimeout <= now`, `!isWithdrawFromOwner` and
+/// `taskProof > currentRunNum * taskUintProof`.
+pub fn withdrawable_refund(
+    info: &TaskInfoOutput,
+    complete_timeout: u64,
+    withdrawn: bool,
+    now: u64,
+) -> Option<U256> {
+    if withdrawn || info.start_time.checked_add(complete_timeout)? > now {
+        return None;
+    }
+    let usage = info
+        .task_uint_proof
+        .checked_mul(U256::from(info.current_run_num))?;
+    (info.task_proof > usage).then(|| info.task_proof - usage)
+}
+
+impl Client {
+    /// Finds the tasks from `from_task` on whose refund can be withdrawn now.
+    pub async fn pending_refunds(&self, from_task: u64) -> Result<RefundScan, anyhow::Error> {
+        let client = match self.block() {
+            Some(_) => self.clone(),
+            None => self.snapshot().await?,
+        };
+        let calls = client.calls();
+        let task_sum = calls.task_sum().await?;
+        let race_timeout = calls.race_timeout().await?;
+        let complete_timeout = calls.complete_timeout().await?;
+        let now = calls.get_curren_time().await?;
+
+        let from_task = from_task.max(1);
+        let mut scan = RefundScan {
+            refunds: vec![],
+            resume_from: task_sum + 1,
+        };
+        for task_id in from_task..=task_sum {
+            let info = calls.task_info(task_id).await?;
+            let withdrawn = calls.is_withdraw_from_owner(task_id).await?;
+            if let Some(amount) = withdrawable_refund(&info, complete_timeout, withdrawn, now) {
+                scan.refunds.push(Refund {
+                    task_id,
+                    publisher: info.publisher,
+                    amount,
+                });
+                continue;
+            }
+            let status = TaskStatus::derive(&info, race_timeout, complete_timeout, withdrawn, now);
+            if !status.is_final() {
+                scan.resume_from = scan.resume_from.min(task_id);
+            }
+        }
+        Ok(scan)
+    }
+
+    /// Withdraws every pending refund from `from_task` on, in task order.
+    /// Failed withdrawals are reported rather than aborting the sweep.
+    pub async fn sweep_refunds(&self, from_task: u64) -> Result<RefundReport, anyhow::Error> {
+        let (owner,) = self.owner().await?;
+        if owner != self.address() {
+            anyhow::bail!(
+                "only the owner {:?} can withdraw refunds, not {:?}",
+                owner,
+                self.address()
+            );
+        }
+        let scan = self.pending_refunds(from_task).await?;
+        let mut report = RefundReport {
+            resume_from: scan.resume_from,
+            ..RefundReport::default()
+        };
+        for refund in scan.refunds {
+            match self.withdraw_ezc(refund.task_id).await {
+                Ok(receipt) if receipt.status == Some(1.into()) => {
+                    log::info!(
+                        "refunded {} EZC of task {} to {:?}",
+                        refund.amount,
+                        refund.task_id,
+                        refund.publisher
+                    );
+                    report.refunded.push((refund, receipt.transaction_hash));
+                }
+                Ok(receipt) => {
+                    let reason = format!("withdrawal reverted in {:?}", receipt.transaction_hash);
+                    report.failed.push((refund, reason));
+                }
+                Err(e) => report.failed.push((refund, e.to_string())),
+            }
+        }
+        // Failed tasks are still refundable and must be scanned again.
+        if let Some(first_failed) = report.failed.iter().map(|(r, _)| r.task_id).min() {
+            report.resume_from = report.resume_from.min(first_failed);
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const START: u64 = 1_660_000_000;
+
+    fn info(current_run_num: u64) -> TaskInfoOutput {
+        TaskInfoOutput {
+            current_run_num,
+            max_run_num: 3,
+            start_time: START,
+            current_running_num: 0,
+            maintain_blocks: 0,
+            task_proof: U256::from(30),
+            task_uint_proof: U256::from(10),
+            publisher: Address::zero(),
+        }
+    }
+
+    #[test]
+    fn test_withdrawable_refund() {
+        let deadline = START + 600;
+        assert_eq!(
+            withdrawable_refund(&info(1), 600, false, deadline - 1),
+            None
+        );
+        assert_eq!(
+            withdrawable_refund(&info(1), 600, false, deadline),
+            Some(U256::from(20))
+        );
+        assert_eq!(withdrawable_refund(&info(1), 600, true, deadline), None);
+        assert_eq!(
+            withdrawable_refund(&info(2), 600, false, deadline),
+            Some(U256::from(10))
+        );
+        // taskProof must exceed the usage, not merely equal it
+        assert_eq!(withdrawable_refund(&info(3), 600, false, deadline), None);
+        assert_eq!(
+            withdrawable_refund(&info(0), u64::MAX, false, u64::MAX),
+            None
+        );
+    }
+
+    #[test]
+    fn test_report_by_publisher() {
+        let alice = Address::from_low_u64_be(1);
+        let bob = Address::from_low_u64_be(2);
+        let refund = |task_id, publisher, amount: u64| {
+            (
+                Refund {
+                    task_id,
+                    publisher,
+                    amount: amount.into(),
+                },
+                H256::zero(),
+            )
+        };
+        let report = RefundReport {
+            refunded: vec![refund(3, alice, 10), refund(4, bob, 5), refund(7, alice, 1)],
+            ..RefundReport::default()
+        };
+        let publishers = report.by_publisher();
+        assert_eq!(publishers[&alice].task_ids, vec![3, 7]);
+        assert_eq!(publishers[&alice].total, U256::from(11));
+        assert_eq!(publishers[&bob].total, U256::from(5));
+        assert_eq!(report.total(), U256::from(16));
+    }
+}
@@ -0,0 +1,132 @@
+//! Revert reasons of failed contract calls.
+//!
+//! DEP reverts with `require` messages such as "Low credit score, no right
+//! to enforce". Nodes return them as a JSON-RPC error whose `data` is the
+//! ABI encoded `Error(string)`, whose message ends with the reason, or both,
+//! depending on the client. A transaction that reverts once mined only has a
+//! failed status; its reason is recovered by replaying it with `eth_call` at
+//! the block it was mined in.
+
+use crate::Client;
+use web3::ethabi::{self, ParamType, Token};
+use web3::types::{Address, BlockId, BlockNumber, Bytes, CallRequest, U256};
+
+/// The selector of `Error(string)`.
+pub const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// Decodes `Error(string)` return data.
+pub fn decode_revert_data(data: &[u8]) -> Option<String> {
+    let encoded = data.strip_prefix(&ERROR_SELECTOR)?;
+    match ethabi::decode(&[ParamType::String], encoded).ok()?.pop() {
+        Some(Token::String(reason)) => Some(reason),
+        _ => None,
+    }
+}
+
+/// The reason in a JSON-RPC error, from its data or its message.
+pub fn rpc_revert_reason(error: &jsonrpc_core::Error) -> Option<String> {
+    let data = error
+        .data
+        .clone()
+        .and_then(|data| serde_json::from_value::<Bytes>(data).ok())
+        .and_then(|data| decode_revert_data(&data.0));
+    if data.is_some() {
+        return data;
+    }
+    let message = error.message.as_str();
+    for prefix in [
+        "execution reverted: ",
+        "VM Exception while processing transaction: revert ",
+    ] {
+        if let Some(reason) = message.strip_prefix(prefix) {
+            return Some(reason.to_string());
+        }
+    }
+    message.contains("revert").then(|| message.to_string())
+}
+
+/// The revert reason behind an error returned by a read or a write, if the
+/// node reported one.
+pub fn revert_reason(error: &anyhow::Error) -> Option<String> {
+    error.chain().find_map(|cause| match cause.downcast_ref() {
+        Some(web3::Error::Rpc(rpc)) => rpc_revert_reason(rpc),
+        _ => None,
+    })
+}
+
+impl Client {
+    /// Replays a call as `from` at `block` and returns why it reverts, or
+    /// `None` when it succeeds or the node gives no reason.
+    pub(crate) async fn replay_revert_reason(
+        &self,
+        from: Address,
+        data: Vec<u8>,
+        gas: Option<U256>,
+        block: u64,
+    ) -> Option<String> {
+        let request = CallRequest {
+            from: Some(from),
+            to: Some(self.contract.address()),
+            gas,
+            data: Some(Bytes(data)),
+            ..CallRequest::default()
+        };
+        let block = BlockId::Number(BlockNumber::Number(block.into()));
+        match self.eth.call(request, Some(block)).await {
+            Ok(_) => None,
+            Err(web3::Error::Rpc(rpc)) => rpc_revert_reason(&rpc),
+            Err(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn encoded(reason: &str) -> Vec<u8> {
+        let mut data = ERROR_SELECTOR.to_vec();
+        data.extend(ethabi::encode(&[Token::String(reason.to_string())]));
+        data
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            decode_revert_data(&encoded("Task is full")).as_deref(),
+            Some("Task is full")
+        );
+        assert_eq!(decode_revert_data(&[0x08, 0xc3]), None);
+        assert_eq!(decode_revert_data(&[0u8; 36]), None);
+    }
+
+    #[test]
+    fn test_rpc_reason() {
+        let mut error = jsonrpc_core::Error::new(jsonrpc_core::ErrorCode::ServerError(3));
+        error.message = "execution reverted".to_string();
+        error.data = Some(json!(Bytes(encoded(
+            "Low credit score, no right to enforce"
+        ))));
+        assert_eq!(
+            rpc_revert_reason(&error).as_deref(),
+            Some("Low credit score, no right to enforce")
+        );
+
+        error.data = None;
+        error.message = "execution reverted: Task is full".to_string();
+        assert_eq!(rpc_revert_reason(&error).as_deref(), Some("Task is full"));
+
+        error.message = "nonce too low".to_string();
+        assert_eq!(rpc_revert_reason(&error), None);
+
+        let wrapped = anyhow::Error::from(web3::contract::Error::Api(web3::Error::Rpc(
+            jsonrpc_core::Error {
+                message: "execution reverted: Task is full".to_string(),
+                ..error
+            },
+        )));
+        assert_eq!(revert_reason(&wrapped).as_deref(), Some("Task is full"));
+        assert_eq!(revert_reason(&anyhow::anyhow!("other")), None);
+    }
+}
@@ -0,0 +1,217 @@
+//! Reward accounting for node earnings.
+//!
+//! Each `completeSubIndexForTask` adds the task's `taskUintProof` to
+//! `userDayReward[node][day]`, where a day is `block.timestamp / 1 days`.
+//! Settlement moves `userRewardPoint[node]` forward; the days from the
+//! pointer (or `startDay`, if later) up to today are unsettled. DEP 1.0.5
+//! never writes `userSettledDay`, so it is not consulted.
+
+use crate::day::DepDay;
+use crate::Client;
+use std::fmt;
+use web3::types::{Address, U256};
+
+/// Decimals of the EZC token.
+pub const EZC_DECIMALS: usize = 18;
+
+/// Formats a raw EZC amount with its decimal point, without trailing zeros.
+pub fn format_ezc(amount: U256) -> String {
+    let unit = U256::exp10(EZC_DECIMALS);
+    let fraction = format!(
+        "{:0>width$}",
+        (amount % unit).to_string(),
+        width = EZC_DECIMALS
+    );
+    let fraction = fraction.trim_end_matches('0');
+    match fraction {
+        "" => (amount / unit).to_string(),
+        fraction => format!("{}.{}", amount / unit, fraction),
+    }
+}
+
+/// Parses a decimal EZC amount such as `1.5` into its raw value.
+pub fn parse_ezc(amount: &str) -> Result<U256, anyhow::Error> {
+    let amount = amount.trim();
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if fraction.len() > EZC_DECIMALS {
+        anyhow::bail!("{} has more than {} decimals", amount, EZC_DECIMALS);
+    }
+    if whole.is_empty() && fraction.is_empty() {
+        anyhow::bail!("empty EZC amount");
+    }
+    let parse = |digits: &str| -> Result<U256, anyhow::Error> {
+        match digits {
+            "" => Ok(U256::zero()),
+            digits => Ok(U256::from_dec_str(digits)?),
+        }
+    };
+    let fraction = format!("{:0<width$}", fraction, width = EZC_DECIMALS);
+    Ok(parse(whole)? * U256::exp10(EZC_DECIMALS) + parse(&fraction)?)
+}
+
+/// A node's earnings on one day next to everything published that day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DayReward {
+    pub day: DepDay,
+    pub earned: U256,
+    /// `dayTotalReward`: the `taskProof` of every task published that day.
+    pub day_total: U256,
+}
+
+impl DayReward {
+    /// The fraction of the day's published rewards the node earned.
+    pub fn share(&self) -> f64 {
+        if self.day_total.is_zero() {
+            return 0.0;
+        }
+        ratio(self.earned, self.day_total)
+    }
+}
+
+/// Earnings aggregated over the week starting on Monday `week_start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeekReward {
+    pub week_start: DepDay,
+    pub earned: U256,
+    pub day_total: U256,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewardSummary {
+    pub node: Address,
+    /// Per-day earnings in `from_day..=to_day`, including days without any.
+    pub days: Vec<DayReward>,
+}
+
+impl RewardSummary {
+    pub fn total(&self) -> U256 {
+        self.days
+            .iter()
+            .fold(U256::zero(), |total, day| total + day.earned)
+    }
+
+    /// Aggregates the days into Monday-to-Sunday weeks.
+    pub fn by_week(&self) -> Vec<WeekReward> {
+        let mut weeks: Vec<WeekReward> = vec![];
+        for day in &self.days {
+            let week_start = day.day.week_start();
+            match weeks.last_mut() {
+                Some(week) if week.week_start == week_start => {
+                    week.earned += day.earned;
+                    week.day_total += day.day_total;
+                }
+                _ => weeks.push(WeekReward {
+                    week_start,
+                    earned: day.earned,
+                    day_total: day.day_total,
+                }),
+            }
+        }
+        weeks
+    }
+}
+
+impl fmt::Display for RewardSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for day in &self.days {
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{:.4}%",
+                day.day,
+                format_ezc(day.earned),
+                format_ezc(day.day_total),
+                day.share() * 100.0
+            )?;
+        }
+        write!(f, "total\t{}", format_ezc(self.total()))
+    }
+}
+
+/// `numerator / denominator` as a float, for amounts too large for `f64`
+/// conversion to be exact.
+pub(crate) fn ratio(numerator: U256, denominator: U256) -> f64 {
+    let scale = U256::exp10(12);
+    (numerator * scale / denominator).as_u128() as f64 / 1e12
+}
+
+impl Client {
+    /// A node's earnings per day in `from_day..=to_day`, read from one block.
+    pub async fn rewards_between(
+        &self,
+        node: Address,
+        from_day: DepDay,
+        to_day: DepDay,
+    ) -> Result<RewardSummary, anyhow::Error> {
+        let client = match self.block() {
+            Some(_) => self.clone(),
+            None => self.snapshot().await?,
+        };
+        let calls = client.calls();
+        let mut days = vec![];
+        for day in DepDay::range(from_day, to_day) {
+            days.push(DayReward {
+                day,
+                earned: calls.user_day_reward(node, day.0).await?,
+                day_total: calls.day_total_reward(day.0).await?,
+            });
+        }
+        Ok(RewardSummary { node, days })
+    }
+
+    /// A node's earnings from its reward pointer up to the current day.
+    pub async fn unsettled_rewards(&self, node: Address) -> Result<RewardSummary, anyhow::Error> {
+        let client = match self.block() {
+            Some(_) => self.clone(),
+            None => self.snapshot().await?,
+        };
+        let calls = client.calls();
+        let from_day =
+            DepDay(calls.user_reward_point(node).await?).max(client.deployment_day().await?);
+        let to_day = client.current_day().await?;
+        client.rewards_between(node, from_day, to_day).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ezc_formatting() {
+        assert_eq!(format_ezc(U256::zero()), "0");
+        assert_eq!(format_ezc(U256::exp10(18) * 3), "3");
+        assert_eq!(format_ezc(U256::exp10(17) * 15), "1.5");
+        assert_eq!(format_ezc(U256::one()), "0.000000000000000001");
+        assert_eq!(parse_ezc("1.5").unwrap(), U256::exp10(17) * 15);
+        assert_eq!(parse_ezc(".25").unwrap(), U256::exp10(16) * 25);
+        assert_eq!(parse_ezc("42").unwrap(), U256::exp10(18) * 42);
+        assert!(parse_ezc("0.0000000000000000001").is_err());
+        assert!(parse_ezc("abc").is_err());
+    }
+
+    #[test]
+    fn test_by_week() {
+        // 19219 is Monday 2022-08-15
+        let summary = RewardSummary {
+            node: Address::zero(),
+            days: DepDay::range(DepDay(19217), DepDay(19226))
+                .map(|day| DayReward {
+                    day,
+                    earned: U256::from(1),
+                    day_total: U256::from(4),
+                })
+                .collect(),
+        };
+        let weeks = summary.by_week();
+        assert_eq!(
+            weeks
+                .iter()
+                .map(|week| week.week_start.0)
+                .collect::<Vec<_>>(),
+            vec![19212, 19219, 19226]
+        );
+        assert_eq!(weeks[1].earned, U256::from(7));
+        assert_eq!(summary.total(), U256::from(10));
+        assert_eq!(summary.days[0].share(), 0.25);
+    }
+}
@@ -0,0 +1,281 @@
+//! Runner version rollouts announced with `updateRunner`.
+//!
+//! `updateRunner(version)` only emits `UpdateRunner(version)`; the contract
+//! neither stores nor validates the string. `RolloutHistory` rebuilds the
+//! announcements from events, `RolloutWatcher` follows new ones, and
+//! `Client::announce_runner_version` refuses malformed versions and
+//! downgrades before anything reaches the chain.
+
+use crate::events::{DepEvent, DepLog, EventWatcher};
+use crate::Client;
+use semver::Version;
+use std::cmp::Ordering;
+use web3::types::{TransactionReceipt, H256};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunnerAnnouncement {
+    /// The string as emitted.
+    pub version: String,
+    /// `None` when the string is not valid semver.
+    pub parsed: Option<Version>,
+    pub block_number: u64,
+    /// Unix time of the block.
+    pub timestamp: u64,
+    pub transaction_hash: H256,
+}
+
+/// How a node's version compares to the latest announcement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeVersionStatus {
+    UpToDate,
+    Behind {
+        latest: Version,
+    },
+    /// Newer than anything announced, e.g. a canary.
+    Ahead {
+        latest: Version,
+    },
+    /// The node reported something that is not semver.
+    Malformed {
+        reason: String,
+    },
+    NoAnnouncement,
+}
+
+/// Parses a version exactly as announcements must be written: plain semver
+/// without a `v` prefix or surrounding whitespace.
+pub fn parse_version(version: &str) -> Result<Version, anyhow::Error> {
+    Version::parse(version).map_err(|e| anyhow::anyhow!("{:?} is not semver: {}", version, e))
+}
+
+/// Announcements in chain order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RolloutHistory {
+    pub announcements: Vec<RunnerAnnouncement>,
+}
+
+impl RolloutHistory {
+    /// The last announcement, malformed or not.
+    pub fn latest(&self) -> Option<&RunnerAnnouncement> {
+        self.announcements.last()
+    }
+
+    /// The last announcement that is valid semver.
+    pub fn latest_valid(&self) -> Option<&RunnerAnnouncement> {
+        self.announcements
+            .iter()
+            .rev()
+            .find(|announcement| announcement.parsed.is_some())
+    }
+
+    /// The highest version ever announced.
+    pub fn highest(&self) -> Option<&Version> {
+        self.announcements
+            .iter()
+            .filter_map(|announcement| announcement.parsed.as_ref())
+            .max()
+    }
+
+    pub fn malformed(&self) -> impl Iterator<Item = &RunnerAnnouncement> {
+        self.announcements
+            .iter()
+            .filter(|announcement| announcement.parsed.is_none())
+    }
+
+    /// Compares a node's self-reported version with the latest valid
+    /// announcement.
+    pub fn compare(&self, node_version: &str) -> NodeVersionStatus {
+        let node_version = match parse_version(node_version.trim()) {
+            Ok(version) => version,
+            Err(e) => {
+                return NodeVersionStatus::Malformed {
+                    reason: e.to_string(),
+                }
+            }
+        };
+        let latest = match self
+            .latest_valid()
+            .and_then(|announcement| announcement.parsed.clone())
+        {
+            Some(latest) => latest,
+            None => return NodeVersionStatus::NoAnnouncement,
+        };
+        match node_version.cmp(&latest) {
+            Ordering::Less => NodeVersionStatus::Behind { latest },
+            Ordering::Equal => NodeVersionStatus::UpToDate,
+            Ordering::Greater => NodeVersionStatus::Ahead { latest },
+        }
+    }
+
+    /// Checks a version before it is announced: it must be semver and, unless
+    /// `allow_downgrade`, higher than every valid announcement so far.
+    pub fn check_next(
+        &self,
+        version: &str,
+        allow_downgrade: bool,
+    ) -> Result<Version, anyhow::Error> {
+        let version = parse_version(version)?;
+        if let Some(highest) = self.highest() {
+            if version == *highest {
+                anyhow::bail!("{} has already been announced", version);
+            }
+            if version < *highest && !allow_downgrade {
+                anyhow::bail!("{} would downgrade runners from {}", version, highest);
+            }
+        }
+        Ok(version)
+    }
+}
+
+async fn announcement(
+    client: &Client,
+    log: &DepLog,
+) -> Result<Option<RunnerAnnouncement>, anyhow::Error> {
+    match &log.event {
+        DepEvent::UpdateRunner(update) => Ok(Some(RunnerAnnouncement {
+            version: update.version.clone(),
+            parsed: Version::parse(&update.version).ok(),
+            block_number: log.block_number,
+            timestamp: client.block_timestamp(log.block_number).await?,
+            transaction_hash: log.transaction_hash,
+        })),
+        _ => Ok(None),
+    }
+}
+
+/// Follows `UpdateRunner` events from a block on.
+pub struct RolloutWatcher {
+    client: Client,
+    watcher: EventWatcher,
+    history: RolloutHistory,
+}
+
+impl RolloutWatcher {
+    pub fn new(client: &Client, from_block: u64) -> Self {
+        RolloutWatcher {
+            client: client.clone(),
+            watcher: EventWatcher::new(client, from_block),
+            history: RolloutHistory::default(),
+        }
+    }
+
+    /// Fetches announcements since the last poll and returns the new ones.
+    pub async fn poll(&mut self) -> Result<Vec<RunnerAnnouncement>, anyhow::Error> {
+        let batch = self.watcher.poll().await?;
+        let mut new = vec![];
+        for log in batch.events() {
+            if let Some(announcement) = announcement(&self.client, &log).await? {
+                self.history.announcements.push(announcement.clone());
+                new.push(announcement);
+            }
+        }
+        Ok(new)
+    }
+
+    pub fn history(&self) -> &RolloutHistory {
+        &self.history
+    }
+
+    /// Whether the last poll reached the head.
+    pub async fn caught_up(&self) -> Result<bool, anyhow::Error> {
+        Ok(self.watcher.next_block() > self.client.block_number().await?)
+    }
+}
+
+impl Client {
+    /// Every runner announcement from `from_block` to the head.
+    pub async fn runner_history(&self, from_block: u64) -> Result<RolloutHistory, anyhow::Error> {
+        let to_block = self.block_number().await?;
+        let mut history = RolloutHistory::default();
+        for log in self.events_between(from_block, to_block).await? {
+            if let Some(announcement) = announcement(self, &log).await? {
+                history.announcements.push(announcement);
+            }
+        }
+        Ok(history)
+    }
+
+    /// Announces `version` after checking that the wallet holds
+    /// `UPDATER_ROLE` and that `history.check_next` accepts it.
+    pub async fn announce_runner_version(
+        &self,
+        version: &str,
+        history: &RolloutHistory,
+        allow_downgrade: bool,
+    ) -> Result<TransactionReceipt, anyhow::Error> {
+        let version = history.check_next(version, allow_downgrade)?;
+        let calls = self.calls();
+        let role = calls.updater_role().await?;
+        if !calls.has_role(role, self.address()).await? {
+            anyhow::bail!("{:?} does not have UPDATER_ROLE", self.address());
+        }
+        self.update_runner(&version.to_string()).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn history(versions: &[&str]) -> RolloutHistory {
+        RolloutHistory {
+            announcements: versions
+                .iter()
+                .enumerate()
+                .map(|(i, version)| RunnerAnnouncement {
+                    version: version.to_string(),
+                    parsed: Version::parse(version).ok(),
+                    block_number: i as u64,
+                    timestamp: 1_660_000_000 + i as u64 * 5,
+                    transaction_hash: H256::zero(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let history = history(&["1.0.0", "1.2.0", "1.1.9", "1.3,0"]);
+        assert_eq!(history.latest().unwrap().version, "1.3,0");
+        assert_eq!(history.latest_valid().unwrap().version, "1.1.9");
+        assert_eq!(history.highest(), Some(&Version::new(1, 2, 0)));
+        assert_eq!(history.malformed().count(), 1);
+
+        assert_eq!(history.compare("1.1.9"), NodeVersionStatus::UpToDate);
+        assert_eq!(
+            history.compare("1.0.0"),
+            NodeVersionStatus::Behind {
+                latest: Version::new(1, 1, 9)
+            }
+        );
+        assert!(matches!(
+            history.compare("1.2.0"),
+            NodeVersionStatus::Ahead { .. }
+        ));
+        assert!(matches!(
+            history.compare("latest"),
+            NodeVersionStatus::Malformed { .. }
+        ));
+        assert_eq!(
+            RolloutHistory::default().compare("1.0.0"),
+            NodeVersionStatus::NoAnnouncement
+        );
+    }
+
+    #[test]
+    fn test_check_next() {
+        let history = history(&["1.0.0", "1.2.0"]);
+        assert_eq!(
+            history.check_next("1.3.0", false).unwrap(),
+            Version::new(1, 3, 0)
+        );
+        assert!(history.check_next("1.2.0", false).is_err());
+        assert!(history.check_next("1.1.0", false).is_err());
+        assert!(history.check_next("1.1.0", true).is_ok());
+        assert!(history.check_next("v1.3.0", false).is_err());
+        assert!(history.check_next("1.3", false).is_err());
+        assert!(history.check_next("1.3.0 ", false).is_err());
+        assert!(history.check_next("1.3.0-rc.1", false).is_ok());
+        assert!(RolloutHistory::default().check_next("0.1.0", false).is_ok());
+    }
+}
@@ -0,0 +1,636 @@
+//! Automated runner agent that races and completes published tasks.
+//!
+//! The runner follows `TaskPublished` events, races every task that passes
+//! its filters within `raceTimeout`, hands the workload to a `TaskExecutor`
+//! and claims the reward with `completeSubIndexForTask` before
+//! `completeTimeout`. Workloads are stopped on `StopTask` and `ResetRunners`
+//! events that the control plane authenticates, and `AddTaskDuration`
+//! extends their lifetime. Progress is persisted so
+//! that a restart neither misses tasks nor races them twice.
+
+use crate::cache::CacheConfig;
+use crate::control::{ControlAction, ControlMessage, ControlPlane, ResetScope};
+use crate::events::{DepEvent, DepLog, EventWatcher, TaskPublished};
+use crate::state;
+use crate::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use web3::types::U256;
+
+pub use crate::executor::{AssignedTask, TaskExecutor, WorkloadStatus};
+
+#[derive(Debug, Clone)]
+pub struct RunnerConfig {
+    /// Only race tasks for these images; `None` accepts any image.
+    pub images: Option<HashSet<String>>,
+    /// Only race tasks paying at least this much `taskUintProof` per run.
+    pub min_unit_proof: U256,
+    pub poll_interval: Duration,
+    /// Stop trying to complete a task this long before `completeTimeout`.
+    pub complete_margin: Duration,
+    pub state_path: PathBuf,
+    /// Where to start following events on the first run; defaults to the
+    /// current head.
+    pub from_block: Option<u64>,
+}
+
+impl Default for RunnerConfig {
+    fn default() -> Self {
+        RunnerConfig {
+            images: None,
+            min_unit_proof: U256::zero(),
+            poll_interval: Duration::from_secs(6),
+            complete_margin: Duration::from_secs(60),
+            state_path: PathBuf::from("runner-state.json"),
+            from_block: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TaskStage {
+    /// The race transaction is being sent. The record is saved first, so a
+    /// crash cannot lose a task that was raced and paid for; whether the race
+    /// landed is checked with `userTask` on the next step.
+    Racing,
+    /// Raced and handed to the executor.
+    Running,
+    /// The workload finished or served its lifetime; completion is still to
+    /// be sent.
+    Executed,
+    Completed,
+    /// The workload was stopped by a control event.
+    Stopped(String),
+    /// The workload failed or could not be completed in time.
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskRecord {
+    pub task: AssignedTask,
+    pub stage: TaskStage,
+}
+
+/// What the runner persists between restarts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunnerState {
+    pub next_block: u64,
+    pub tasks: BTreeMap<u64, TaskRecord>,
+    /// Published tasks still to be considered; a task stays here until it
+    /// was raced or skipped, so a failed read is retried on the next step.
+    #[serde(default)]
+    pub pending: BTreeMap<u64, TaskPublished>,
+    /// Control events still to be obeyed; an event stays here until it was
+    /// authenticated and applied, so an RPC error is retried on the next
+    /// step.
+    #[serde(default)]
+    pub control: Vec<DepLog>,
+}
+
+impl RunnerState {
+    pub fn load(path: &Path) -> Result<Option<RunnerState>, anyhow::Error> {
+        state::load(path)
+    }
+
+    /// Writes the state atomically, so a crash never leaves a torn file.
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        state::save(self, path)
+    }
+}
+
+pub struct Runner<E: TaskExecutor> {
+    client: Client,
+    executor: E,
+    config: RunnerConfig,
+    state: RunnerState,
+    watcher: EventWatcher,
+    control: ControlPlane,
+}
+
+impl<E: TaskExecutor> Runner<E> {
+    /// Loads persisted state. Workloads that were running before a restart
+    /// are started again on the first step.
+    pub async fn new(
+        client: &Client,
+        executor: E,
+        config: RunnerConfig,
+    ) -> Result<Self, anyhow::Error> {
+        let client = match client.cache {
+            Some(_) => client.clone(),
+            None => client.with_cache(CacheConfig::default()),
+        };
+        let state = match RunnerState::load(&config.state_path)? {
+            Some(state) => state,
+            None => RunnerState {
+                next_block: match config.from_block {
+                    Some(block) => block,
+                    None => client.eth.block_number().await?.as_u64() + 1,
+                },
+                tasks: BTreeMap::new(),
+                pending: BTreeMap::new(),
+                control: vec![],
+            },
+        };
+        let watcher = EventWatcher::new(&client, state.next_block);
+        let control = ControlPlane::new(&client);
+        Ok(Runner {
+            client,
+            executor,
+            config,
+            state,
+            watcher,
+            control,
+        })
+    }
+
+    pub fn state(&self) -> &RunnerState {
+        &self.state
+    }
+
+    pub fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    /// Polls forever, logging errors instead of giving up.
+    pub async fn run(mut self) -> Result<(), anyhow::Error> {
+        loop {
+            if let Err(e) = self.step().await {
+                log::warn!("runner step failed: {}", e);
+            }
+            tokio::time::sleep(self.config.poll_interval).await;
+        }
+    }
+
+    /// Races newly published tasks, reacts to control events, supervises
+    /// running workloads and completes finished ones. Failures are handled
+    /// per task, so completions are always attempted and the state is always
+    /// saved; the error of reading new events, if any, is returned last.
+    pub async fn step(&mut self) -> Result<(), anyhow::Error> {
+        let followed = self.follow().await;
+        self.obey_pending().await;
+        self.consider_pending().await;
+        self.supervise().await;
+        let executed: Vec<AssignedTask> = self
+            .state
+            .tasks
+            .values()
+            .filter(|record| record.stage == TaskStage::Executed)
+            .map(|record| record.task.clone())
+            .collect();
+        for task in executed {
+            if let Err(e) = self.complete(&task).await {
+                log::warn!("failed to complete task {}: {}", task.task_id, e);
+            }
+        }
+        self.state.save(&self.config.state_path)?;
+        followed
+    }
+
+    /// Applies the events since the last poll.
+    async fn follow(&mut self) -> Result<(), anyhow::Error> {
+        let batch = self.watcher.poll().await?;
+        for log in batch.events() {
+            self.handle(&log).await;
+        }
+        self.state.next_block = self.watcher.next_block();
+        Ok(())
+    }
+
+    async fn handle(&mut self, log: &DepLog) {
+        match &log.event {
+            DepEvent::TaskPublished(published) if self.accepts(published) => {
+                self.state
+                    .pending
+                    .insert(published.task_id, published.clone());
+            }
+            DepEvent::StopTask(_) | DepEvent::ResetRunners(_) => {
+                self.state.control.push(log.clone());
+            }
+            DepEvent::AddTaskDuration(extension) => {
+                if let Some(record) = self.state.tasks.get_mut(&extension.task_id) {
+                    record.task.maintain_blocks += extension.maintain_extra_blocks;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Obeys the pending control events in order, keeping those that failed
+    /// for the next step.
+    async fn obey_pending(&mut self) {
+        let control = std::mem::take(&mut self.state.control);
+        for log in control {
+            if let Err(e) = self.obey(&log).await {
+                log::warn!(
+                    "failed to handle control event in {:?}, will retry: {}",
+                    log.transaction_hash,
+                    e
+                );
+                self.state.control.push(log);
+            }
+        }
+    }
+
+    /// Considers every pending task, keeping those that failed for the next
+    /// step.
+    async fn consider_pending(&mut self) {
+        let pending: Vec<TaskPublished> = self.state.pending.values().cloned().collect();
+        for published in pending {
+            let task_id = published.task_id;
+            match self.consider(published).await {
+                Ok(()) => {
+                    self.state.pending.remove(&task_id);
+                }
+                Err(e) => log::warn!("failed to race task {}, will retry: {}", task_id, e),
+            }
+        }
+    }
+
+    fn with_stage(&self, stage: TaskStage) -> Vec<u64> {
+        self.state
+            .tasks
+            .values()
+            .filter(|record| record.stage == stage)
+            .map(|record| record.task.task_id)
+            .collect()
+    }
+
+    fn running(&self) -> Vec<u64> {
+        self.with_stage(TaskStage::Running)
+    }
+
+    async fn supervise(&mut self) {
+        match self.client.get_current_time().await {
+            Ok((now,)) => self.supervise_at(now).await,
+            Err(e) => log::warn!("failed to read the contract time: {}", e),
+        }
+    }
+
+    /// Resolves interrupted races, then polls every running workload as of
+    /// contract time `now`. A workload whose executor fails is marked
+    /// `Failed` without affecting the others.
+    pub async fn supervise_at(&mut self, now: u64) {
+        for task_id in self.with_stage(TaskStage::Racing) {
+            if let Err(e) = self.resolve_race(task_id, now).await {
+                log::warn!("failed to check the race for task {}: {}", task_id, e);
+            }
+        }
+        for task_id in self.running() {
+            if let Err(e) = self.supervise_task(task_id, now).await {
+                log::warn!("workload of task {} failed: {}", task_id, e);
+                self.set_stage(task_id, TaskStage::Failed(e.to_string()));
+            }
+        }
+    }
+
+    /// Moves a task whose race outcome is unknown to `Running` once the
+    /// chain shows the race, or drops it once the race window has closed.
+    async fn resolve_race(&mut self, task_id: u64, now: u64) -> Result<(), anyhow::Error> {
+        let (raced,) = self
+            .client
+            .user_task(self.client.address(), task_id)
+            .await?;
+        if raced {
+            log::info!("race for task {} landed", task_id);
+            self.set_stage(task_id, TaskStage::Running);
+            return Ok(());
+        }
+        let (race_timeout,) = self.client.race_timeout().await?;
+        let start_time = self.state.tasks[&task_id].task.start_time;
+        if start_time.saturating_add(race_timeout) < now {
+            self.state.tasks.remove(&task_id);
+        }
+        Ok(())
+    }
+
+    /// Stops a workload whose `maintainBlocks` lifetime has run out and
+    /// restarts one the executor lost.
+    async fn supervise_task(&mut self, task_id: u64, now: u64) -> Result<(), anyhow::Error> {
+        let task = self.state.tasks[&task_id].task.clone();
+        if task
+            .expires_at()
+            .is_some_and(|expires_at| now >= expires_at)
+        {
+            self.executor.stop(task_id).await?;
+            self.set_stage(task_id, TaskStage::Executed);
+            return Ok(());
+        }
+        match self.executor.poll(task_id).await? {
+            WorkloadStatus::Running => {}
+            WorkloadStatus::Succeeded => self.set_stage(task_id, TaskStage::Executed),
+            WorkloadStatus::Failed(e) => self.set_stage(task_id, TaskStage::Failed(e)),
+            WorkloadStatus::Unknown => {
+                log::info!("restarting workload for task {}", task_id);
+                self.executor.start(&task).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops workloads for authenticated control events and flags the rest.
+    async fn obey(&mut self, log: &DepLog) -> Result<(), anyhow::Error> {
+        match self.control.authenticate(log).await? {
+            Some(message) => self.apply_control(message).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Stops the workloads an authenticated control message addresses.
+    pub async fn apply_control(&mut self, message: ControlMessage) -> Result<(), anyhow::Error> {
+        match message {
+            ControlMessage::Authorized {
+                action: ControlAction::StopTask { task_id },
+                ..
+            } => self.stop(task_id, "StopTask event").await,
+            ControlMessage::Authorized {
+                action: action @ ControlAction::ResetRunners { .. },
+                ..
+            } if action.addresses(self.client.address()) => {
+                for task_id in self.running() {
+                    if let ControlAction::ResetRunners {
+                        scope: ResetScope::PublishedBy(sender),
+                        ..
+                    } = action
+                    {
+                        let info = self.client.calls().task_info(task_id).await?;
+                        if info.publisher != sender {
+                            continue;
+                        }
+                    }
+                    self.stop(task_id, "ResetRunners event").await;
+                }
+            }
+            ControlMessage::Rejected {
+                sender,
+                reason,
+                transaction_hash,
+                ..
+            } => log::warn!(
+                "ignoring unauthorized control event in {:?} from {:?}: {}",
+                transaction_hash,
+                sender,
+                reason
+            ),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn stop(&mut self, task_id: u64, reason: &str) {
+        if !self.running().contains(&task_id) {
+            return;
+        }
+        match self.executor.stop(task_id).await {
+            Ok(()) => self.set_stage(task_id, TaskStage::Stopped(reason.to_string())),
+            Err(e) => log::warn!("failed to stop task {}: {}", task_id, e),
+        }
+    }
+
+    /// Whether a published task passes the receiver and image filters.
+    pub fn accepts(&self, task: &TaskPublished) -> bool {
+        let addressed =
+            task.receivers.is_empty() || task.receivers.contains(&self.client.address());
+        let image_allowed = self
+            .config
+            .images
+            .as_ref()
+            .is_none_or(|images| images.contains(&task.url));
+        addressed && image_allowed && !self.state.tasks.contains_key(&task.task_id)
+    }
+
+    /// Races a published task if it still qualifies. `Ok` means the task was
+    /// dealt with, raced or skipped; an error leaves it pending.
+    async fn consider(&mut self, published: TaskPublished) -> Result<(), anyhow::Error> {
+        if !self.accepts(&published) {
+            return Ok(());
+        }
+        let info = self.client.calls().task_info(published.task_id).await?;
+        if info.task_uint_proof < self.config.min_unit_proof {
+            log::info!(
+                "skipping task {}: pays {} per run",
+                published.task_id,
+                info.task_uint_proof
+            );
+            return Ok(());
+        }
+        let (race_timeout,) = self.client.race_timeout().await?;
+        let (now,) = self.client.get_current_time().await?;
+        if info.current_run_num >= info.max_run_num
+            || info.start_time.saturating_add(race_timeout) < now
+        {
+            return Ok(());
+        }
+        let credit = self.client.credit_check(self.client.address()).await?;
+        if !credit.eligible() {
+            log::warn!(
+                "skipping task {}: credit score {} is below the threshold {}",
+                published.task_id,
+                credit.score,
+                credit.threshold
+            );
+            return Ok(());
+        }
+        let task = AssignedTask {
+            task_id: published.task_id,
+            url: published.url,
+            options: published.options,
+            start_time: info.start_time,
+            maintain_blocks: info.maintain_blocks,
+        };
+        let task_id = task.task_id;
+        self.state.tasks.insert(
+            task_id,
+            TaskRecord {
+                task: task.clone(),
+                stage: TaskStage::Racing,
+            },
+        );
+        if let Err(e) = self.state.save(&self.config.state_path) {
+            self.state.tasks.remove(&task_id);
+            return Err(e);
+        }
+        match self.client.race_sub_index_for_task(task_id).await {
+            Ok(receipt) if receipt.status == Some(1.into()) => {
+                log::info!("raced task {} in {:?}", task_id, receipt.transaction_hash);
+                self.set_stage(task_id, TaskStage::Running);
+                self.state.save(&self.config.state_path)?;
+                if let Err(e) = self.executor.start(&task).await {
+                    self.set_stage(task_id, TaskStage::Failed(e.to_string()));
+                }
+            }
+            Ok(receipt) => {
+                log::info!(
+                    "lost race for task {} in {:?}",
+                    task_id,
+                    receipt.transaction_hash
+                );
+                self.state.tasks.remove(&task_id);
+            }
+            // the transaction may still land; `resolve_race` finds out
+            Err(e) => log::info!("race for task {} failed: {}", task_id, e),
+        }
+        Ok(())
+    }
+
+    async fn complete(&mut self, task: &AssignedTask) -> Result<(), anyhow::Error> {
+        let (complete_timeout,) = self.client.complete_timeout().await?;
+        let (now,) = self.client.get_current_time().await?;
+        let deadline = task.start_time + complete_timeout;
+        if now + self.config.complete_margin.as_secs() > deadline {
+            self.set_stage(
+                task.task_id,
+                TaskStage::Failed("completeTimeout passed before execution finished".to_string()),
+            );
+            return Ok(());
+        }
+        let receipt = self
+            .client
+            .complete_sub_index_for_task(task.task_id)
+            .await?;
+        let stage = if receipt.status == Some(1.into()) {
+            log::info!(
+                "completed task {} in {:?}",
+                task.task_id,
+                receipt.transaction_hash
+            );
+            TaskStage::Completed
+        } else {
+            TaskStage::Failed(format!(
+                "completion reverted in {:?}",
+                receipt.transaction_hash
+            ))
+        };
+        self.set_stage(task.task_id, stage);
+        Ok(())
+    }
+
+    fn set_stage(&mut self, task_id: u64, stage: TaskStage) {
+        if let Some(record) = self.state.tasks.get_mut(&task_id) {
+            record.stage = stage;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::abi::AbiVersion;
+    use crate::control::Authority;
+    use crate::executor::MockExecutor;
+    use ethers::signers::LocalWallet;
+    use web3::types::{Address, H256};
+
+    fn assigned(task_id: u64, maintain_blocks: u64) -> AssignedTask {
+        AssignedTask {
+            task_id,
+            url: "xbgxwh/oracle_price:1.0.3".to_string(),
+            options: String::new(),
+            start_time: 1_660_000_000,
+            maintain_blocks,
+        }
+    }
+
+    /// A runner whose workloads are already running. It never reaches the
+    /// node, so only offline paths may be exercised.
+    async fn runner(name: &str, tasks: &[AssignedTask]) -> Runner<MockExecutor> {
+        let client = Client::with_abi_version(
+            "http://127.0.0.1:1",
+            "9397AA12576cEc2A37C60f76d2FB31b31b5E5c7F",
+            AbiVersion::LATEST,
+            LocalWallet::new(&mut rand::thread_rng()),
+        )
+        .unwrap();
+        let state_path =
+            std::env::temp_dir().join(format!("runner-{}-{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&state_path);
+        let config = RunnerConfig {
+            state_path,
+            from_block: Some(1),
+            ..RunnerConfig::default()
+        };
+        let mut runner = Runner::new(&client, MockExecutor::new(), config)
+            .await
+            .unwrap();
+        for task in tasks {
+            runner.executor.start(task).await.unwrap();
+            runner.state.tasks.insert(
+                task.task_id,
+                TaskRecord {
+                    task: task.clone(),
+                    stage: TaskStage::Running,
+                },
+            );
+        }
+        runner
+    }
+
+    fn stage(runner: &Runner<MockExecutor>, task_id: u64) -> &TaskStage {
+        &runner.state().tasks[&task_id].stage
+    }
+
+    #[tokio::test]
+    async fn test_stop_task() {
+        let mut runner = runner("stop", &[assigned(1, 600), assigned(2, 600)]).await;
+        let message = ControlMessage::Authorized {
+            action: ControlAction::StopTask { task_id: 1 },
+            sender: Address::repeat_byte(1),
+            authority: Authority::Publisher,
+            block_number: 2,
+            transaction_hash: H256::zero(),
+        };
+        runner.apply_control(message).await.unwrap();
+        assert_eq!(runner.executor().stopped(), vec![1]);
+        assert_eq!(
+            stage(&runner, 1),
+            &TaskStage::Stopped("StopTask event".to_string())
+        );
+        assert_eq!(stage(&runner, 2), &TaskStage::Running);
+    }
+
+    #[tokio::test]
+    async fn test_expired_workload() {
+        let task = assigned(1, 600);
+        let expires_at = task.expires_at().unwrap();
+        let mut runner = runner("expired", &[task]).await;
+
+        runner.supervise_at(expires_at - 1).await;
+        assert_eq!(stage(&runner, 1), &TaskStage::Running);
+        assert!(runner.executor().stopped().is_empty());
+
+        runner.supervise_at(expires_at).await;
+        assert_eq!(stage(&runner, 1), &TaskStage::Executed);
+        assert_eq!(runner.executor().stopped(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_executor_error() {
+        let mut runner = runner("error", &[assigned(1, 600), assigned(2, 0)]).await;
+        runner.executor().fail(1, "container engine unreachable");
+        runner.executor().finish(2, WorkloadStatus::Succeeded);
+
+        runner.supervise_at(1_660_000_100).await;
+        assert_eq!(
+            stage(&runner, 1),
+            &TaskStage::Failed("container engine unreachable".to_string())
+        );
+        assert_eq!(stage(&runner, 2), &TaskStage::Executed);
+    }
+
+    #[tokio::test]
+    async fn test_control_retry() {
+        let mut runner = runner("control", &[assigned(1, 600)]).await;
+        let stop = DepLog {
+            block_number: 2,
+            transaction_hash: H256::zero(),
+            log_index: 0,
+            event: DepEvent::StopTask(crate::events::StopTask { task_id: 1.into() }),
+        };
+        runner.handle(&stop).await;
+        // authenticating needs the node, so the event is kept for a retry
+        runner.obey_pending().await;
+        assert_eq!(runner.state().control, vec![stop]);
+        assert_eq!(stage(&runner, 1), &TaskStage::Running);
+    }
+}
@@ -0,0 +1,44 @@
+//! JSON state files shared by the long-running services.
+//!
+//! The runner, the keep-alive service and whitelist plans persist their
+//! progress as pretty-printed JSON. Files are written to a sibling `.tmp`
+//! file and renamed over the old one, so a crash never leaves a torn file.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// Reads the state at `path`, or `None` if there is no file yet.
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, anyhow::Error> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+}
+
+/// Writes the state to `path` atomically.
+pub fn save<T: Serialize>(state: &T, path: &Path) -> Result<(), anyhow::Error> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(state)?)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("dep-state-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(load::<BTreeMap<u64, String>>(&path).unwrap(), None);
+        let state = BTreeMap::from([(3, "running".to_string()), (7, "lost".to_string())]);
+        save(&state, &path).unwrap();
+        assert!(!path.with_extension("tmp").exists());
+        assert_eq!(load(&path).unwrap(), Some(state));
+        fs::remove_file(&path).unwrap();
+    }
+}
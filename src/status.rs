@@ -0,0 +1,241 @@
+//! Task lifecycle derived from chain data.
+//!
+//! The contract has no notion of a task's stage; it only checks timestamps
+//! and counters in each call. `TaskStatus` applies those same checks so that
+//! callers can tell what a task is waiting for and when that changes:
+//!
+//! - `raceSubIndexForTask` is open while `startTime + raceTimeout >= now` and
+//!   `currentRunNum < maxRunNum`.
+//! - `completeSubIndexForTask` is open while `startTime + completeTimeout >= now`.
+//! - `withdrawEZC` is open once `startTime + completeTimeout <= now`, until
+//!   `isWithdrawFromOwner`, if `taskProof > currentRunNum * taskUintProof`.
+//! - Workloads are maintained until `startTime + maintainBlocks * 5`, the
+//!   point `getTaskRemainingTime` counts from.
+
+use crate::bindings::TaskInfoOutput;
+use crate::executor::BLOCK_TIME_SECS;
+use crate::Client;
+use std::time::Duration;
+use web3::types::U256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskStatus {
+    /// Nodes can still race for `open_slots` runs.
+    Racing {
+        open_slots: u64,
+        closes_in: Duration,
+    },
+    /// Every run has been raced and `running` of them are not completed yet.
+    Filled {
+        running: u64,
+        deadline_in: Duration,
+        maintained_for: Option<Duration>,
+    },
+    /// The race closed with runs left over and `running` raced runs are not
+    /// completed yet.
+    Running {
+        running: u64,
+        deadline_in: Duration,
+        maintained_for: Option<Duration>,
+    },
+    /// Every raced run has been completed. `withdrawable_in` is set while a
+    /// refund for unused runs is still to become withdrawable.
+    Completed {
+        withdrawable_in: Option<Duration>,
+    },
+    /// The task can no longer be raced or completed without every run having
+    /// been completed; `withdrawable_in` as for `Completed`.
+    Expired {
+        withdrawable_in: Option<Duration>,
+    },
+    /// The owner can refund `refund` to the publisher with `withdrawEZC`.
+    Withdrawable {
+        refund: U256,
+    },
+    Withdrawn,
+}
+
+impl TaskStatus {
+    /// Derives the status from a `taskInfo` entry at unix time `now`.
+    pub fn derive(
+        info: &TaskInfoOutput,
+        race_timeout: u64,
+        complete_timeout: u64,
+        withdrawn: bool,
+        now: u64,
+    ) -> TaskStatus {
+        if withdrawn {
+            return TaskStatus::Withdrawn;
+        }
+        let race_closes = info.start_time + race_timeout + 1;
+        let deadline = info.start_time + complete_timeout;
+        let usage = info.task_uint_proof * info.current_run_num;
+        let refund = info.task_proof.saturating_sub(usage);
+        let until = |at: u64| Duration::from_secs(at.saturating_sub(now));
+
+        if now < race_closes && info.current_run_num < info.max_run_num {
+            return TaskStatus::Racing {
+                open_slots: info.max_run_num - info.current_run_num,
+                closes_in: until(race_closes),
+            };
+        }
+        if now <= deadline && info.current_running_num > 0 {
+            let maintained_until = info.start_time + info.maintain_blocks * BLOCK_TIME_SECS;
+            let maintained_for = (info.maintain_blocks > 0 && maintained_until > now)
+                .then(|| until(maintained_until));
+            return if info.current_run_num >= info.max_run_num {
+                TaskStatus::Filled {
+                    running: info.current_running_num,
+                    deadline_in: until(deadline + 1),
+                    maintained_for,
+                }
+            } else {
+                TaskStatus::Running {
+                    running: info.current_running_num,
+                    deadline_in: until(deadline + 1),
+                    maintained_for,
+                }
+            };
+        }
+        if now >= deadline && !refund.is_zero() {
+            return TaskStatus::Withdrawable { refund };
+        }
+        let withdrawable_in = (!refund.is_zero()).then(|| until(deadline));
+        if info.current_run_num > 0 && info.current_running_num == 0 {
+            TaskStatus::Completed { withdrawable_in }
+        } else {
+            TaskStatus::Expired { withdrawable_in }
+        }
+    }
+
+    /// Time until the stage changes without any transaction, or `None` when
+    /// only a transaction (or nothing) can move the task on.
+    pub fn next_transition(&self) -> Option<Duration> {
+        match self {
+            TaskStatus::Racing { closes_in, .. } => Some(*closes_in),
+            TaskStatus::Filled { deadline_in, .. } | TaskStatus::Running { deadline_in, .. } => {
+                Some(*deadline_in)
+            }
+            TaskStatus::Completed { withdrawable_in } | TaskStatus::Expired { withdrawable_in } => {
+                *withdrawable_in
+            }
+            TaskStatus::Withdrawable { .. } | TaskStatus::Withdrawn => None,
+        }
+    }
+
+    /// Whether the task can never change stage again.
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            TaskStatus::Withdrawn
+                | TaskStatus::Completed {
+                    withdrawable_in: None
+                }
+                | TaskStatus::Expired {
+                    withdrawable_in: None
+                }
+        )
+    }
+}
+
+impl Client {
+    /// The lifecycle stage of a task, read from a single block.
+    pub async fn task_status(&self, task_id: u64) -> Result<TaskStatus, anyhow::Error> {
+        let client = match self.block() {
+            Some(_) => self.clone(),
+            None => self.snapshot().await?,
+        };
+        let calls = client.calls();
+        let task_sum = calls.task_sum().await?;
+        if task_id == 0 || task_id > task_sum {
+            anyhow::bail!(
+                "task {} does not exist, the last task is {}",
+                task_id,
+                task_sum
+            );
+        }
+        let info = calls.task_info(task_id).await?;
+        Ok(TaskStatus::derive(
+            &info,
+            calls.race_timeout().await?,
+            calls.complete_timeout().await?,
+            calls.is_withdraw_from_owner(task_id).await?,
+            calls.get_curren_time().await?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use web3::types::Address;
+
+    const START: u64 = 1_660_000_000;
+
+    fn info(current_run_num: u64, current_running_num: u64) -> TaskInfoOutput {
+        TaskInfoOutput {
+            current_run_num,
+            max_run_num: 3,
+            start_time: START,
+            current_running_num,
+            maintain_blocks: 60,
+            task_proof: U256::from(30),
+            task_uint_proof: U256::from(10),
+            publisher: Address::zero(),
+        }
+    }
+
+    fn status(info: &TaskInfoOutput, now: u64) -> TaskStatus {
+        TaskStatus::derive(info, 600, 3600, false, START + now)
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        assert_eq!(
+            status(&info(1, 1), 100),
+            TaskStatus::Racing {
+                open_slots: 2,
+                closes_in: Duration::from_secs(501)
+            }
+        );
+        assert_eq!(
+            status(&info(3, 2), 100),
+            TaskStatus::Filled {
+                running: 2,
+                deadline_in: Duration::from_secs(3501),
+                maintained_for: Some(Duration::from_secs(200))
+            }
+        );
+        assert_eq!(
+            status(&info(2, 1), 700),
+            TaskStatus::Running {
+                running: 1,
+                deadline_in: Duration::from_secs(2901),
+                maintained_for: None
+            }
+        );
+        assert_eq!(
+            status(&info(2, 0), 700),
+            TaskStatus::Completed {
+                withdrawable_in: Some(Duration::from_secs(2900))
+            }
+        );
+        assert_eq!(
+            status(&info(2, 0), 3600),
+            TaskStatus::Withdrawable {
+                refund: U256::from(10)
+            }
+        );
+        assert!(status(&info(3, 0), 700).is_final());
+        assert_eq!(
+            status(&info(3, 1), 3601),
+            TaskStatus::Expired {
+                withdrawable_in: None
+            }
+        );
+        assert_eq!(
+            TaskStatus::derive(&info(2, 0), 600, 3600, true, START + 4000),
+            TaskStatus::Withdrawn
+        );
+    }
+}
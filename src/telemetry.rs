@@ -0,0 +1,51 @@
+//! OpenTelemetry export of the `call` and `send` spans, behind the `otel`
+//! feature.
+//!
+//! `init` installs a `tracing` subscriber that hands every span at or above
+//! the given level to an OTLP/gRPC exporter, so contract reads and writes
+//! show up next to the caller's own spans in any OTLP collector. Records of
+//! the `log` crate are unaffected, while `tracing` events such as "call
+//! reverted" are recorded on their spans instead of being logged. The
+//! exporter needs a Tokio runtime.
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// The service name spans are reported under.
+pub const SERVICE_NAME: &str = "dep";
+
+/// Flushes and shuts the exporter down when dropped.
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            log::warn!("failed to flush spans: {}", e);
+        }
+    }
+}
+
+/// Exports spans at `level` and above to the OTLP collector at `endpoint`,
+/// e.g. `http://localhost:4317`. Fails if a subscriber is already installed.
+pub fn init(endpoint: &str, level: LevelFilter) -> Result<Telemetry, anyhow::Error> {
+    let exporter = SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build();
+    tracing_subscriber::registry()
+        .with(level)
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)))
+        .try_init()?;
+    Ok(Telemetry { provider })
+}
@@ -0,0 +1,134 @@
+//! JSON-RPC transport that counts requests, errors and latency per method.
+//!
+//! Every `Client` talks to the node through `MeteredHttp`, so the counters
+//! cover contract reads and writes as well as log, block and receipt
+//! queries. An error is anything the call returned as `Err`: network
+//! failures, JSON-RPC errors and reverted `eth_call`s alike.
+
+use jsonrpc_core::{Call, Value};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use web3::transports::Http;
+use web3::{RequestId, Transport};
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+pub const LATENCY_BUCKETS: [f64; 9] = [0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MethodStats {
+    pub requests: u64,
+    pub errors: u64,
+    /// Total time spent waiting for responses.
+    pub seconds: f64,
+    /// Requests that took at most the matching `LATENCY_BUCKETS` bound.
+    pub buckets: [u64; LATENCY_BUCKETS.len()],
+}
+
+impl MethodStats {
+    fn record(&mut self, elapsed: Duration, ok: bool) {
+        let seconds = elapsed.as_secs_f64();
+        self.requests += 1;
+        if !ok {
+            self.errors += 1;
+        }
+        self.seconds += seconds;
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+    }
+}
+
+/// Counters shared by every clone of a transport.
+#[derive(Debug, Default)]
+pub struct RpcStats {
+    methods: Mutex<BTreeMap<String, MethodStats>>,
+}
+
+impl RpcStats {
+    pub fn record(&self, method: &str, elapsed: Duration, ok: bool) {
+        let mut methods = self.methods.lock().unwrap();
+        match methods.get_mut(method) {
+            Some(stats) => stats.record(elapsed, ok),
+            None => {
+                let mut stats = MethodStats::default();
+                stats.record(elapsed, ok);
+                methods.insert(method.to_string(), stats);
+            }
+        }
+    }
+
+    /// The counters so far, by JSON-RPC method.
+    pub fn snapshot(&self) -> BTreeMap<String, MethodStats> {
+        self.methods.lock().unwrap().clone()
+    }
+}
+
+/// An HTTP transport recording every request in its `RpcStats`.
+#[derive(Debug, Clone)]
+pub struct MeteredHttp {
+    inner: Http,
+    stats: Arc<RpcStats>,
+}
+
+impl MeteredHttp {
+    pub fn new(url: &str) -> Result<Self, anyhow::Error> {
+        Ok(MeteredHttp {
+            inner: Http::new(url)?,
+            stats: Arc::new(RpcStats::default()),
+        })
+    }
+
+    pub fn stats(&self) -> Arc<RpcStats> {
+        self.stats.clone()
+    }
+}
+
+impl Transport for MeteredHttp {
+    type Out = Pin<Box<dyn Future<Output = web3::Result<Value>> + Send>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let method = match &request {
+            Call::MethodCall(call) => call.method.clone(),
+            Call::Notification(notification) => notification.method.clone(),
+            Call::Invalid { .. } => "invalid".to_string(),
+        };
+        let stats = self.stats.clone();
+        let response = self.inner.send(id, request);
+        Box::pin(async move {
+            let start = Instant::now();
+            let result = response.await;
+            stats.record(&method, start.elapsed(), result.is_ok());
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let stats = RpcStats::default();
+        stats.record("eth_call", Duration::from_millis(30), true);
+        stats.record("eth_call", Duration::from_secs(3), false);
+        stats.record("eth_blockNumber", Duration::from_secs(20), true);
+        let snapshot = stats.snapshot();
+
+        let call = &snapshot["eth_call"];
+        assert_eq!(call.requests, 2);
+        assert_eq!(call.errors, 1);
+        assert!((call.seconds - 3.03).abs() < 1e-9);
+        assert_eq!(call.buckets, [0, 1, 1, 1, 1, 1, 1, 2, 2]);
+        assert_eq!(snapshot["eth_blockNumber"].buckets, [0; 9]);
+    }
+}
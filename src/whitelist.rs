@@ -0,0 +1,434 @@
+//! Bulk administration of the publisher address whitelist.
+//!
+//! `setAddressWhitelist` toggles one address per transaction and emits no
+//! event, so the whitelisted set is recovered from the indexed
+//! `setAddressWhitelist` calls. A desired allowlist is diffed against it,
+//! with every address involved re-read from the contract, and the resulting
+//! `WhitelistPlan` is applied one transaction at a time. The plan is saved
+//! after each transaction so an interrupted run resumes where it stopped.
+
+use crate::state;
+use crate::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use web3::ethabi::Token;
+use web3::types::{Address, H256};
+
+/// The whitelist an operator wants, keyed by address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DesiredWhitelist {
+    /// `false` marks addresses that must be revoked.
+    pub entries: BTreeMap<Address, bool>,
+}
+
+impl DesiredWhitelist {
+    /// Parses JSON when the text starts with `[` or `{`, CSV otherwise.
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        match text.trim_start().chars().next() {
+            Some('[') | Some('{') => DesiredWhitelist::from_json(text),
+            _ => DesiredWhitelist::from_csv(text),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        DesiredWhitelist::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Rows of `address[,allowed]`, where `allowed` defaults to true. A
+    /// header row, blank lines and `#` comments are skipped.
+    pub fn from_csv(text: &str) -> Result<Self, anyhow::Error> {
+        let mut desired = DesiredWhitelist::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut columns = line.split(',').map(str::trim);
+            let address = columns.next().unwrap_or_default();
+            let address = match parse_address(address) {
+                Ok(address) => address,
+                Err(_) if number == 0 => continue,
+                Err(e) => anyhow::bail!("line {}: {}", number + 1, e),
+            };
+            let allowed = match columns.next() {
+                None | Some("") => true,
+                Some(allowed) => parse_bool(allowed).ok_or_else(|| {
+                    anyhow::anyhow!("line {}: invalid flag {}", number + 1, allowed)
+                })?,
+            };
+            desired.insert(address, allowed)?;
+        }
+        Ok(desired)
+    }
+
+    /// A list of addresses, a map of address to flag, or a list of
+    /// `{"address": .., "allowed": ..}` objects.
+    pub fn from_json(text: &str) -> Result<Self, anyhow::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Entry {
+            Address(String),
+            Flagged {
+                address: String,
+                #[serde(default = "allowed_by_default")]
+                allowed: bool,
+            },
+        }
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Document {
+            List(Vec<Entry>),
+            Map(BTreeMap<String, bool>),
+        }
+        fn allowed_by_default() -> bool {
+            true
+        }
+
+        let mut desired = DesiredWhitelist::default();
+        match serde_json::from_str(text)? {
+            Document::List(entries) => {
+                for entry in entries {
+                    match entry {
+                        Entry::Address(address) => {
+                            desired.insert(parse_address(&address)?, true)?
+                        }
+                        Entry::Flagged { address, allowed } => {
+                            desired.insert(parse_address(&address)?, allowed)?
+                        }
+                    }
+                }
+            }
+            Document::Map(entries) => {
+                for (address, allowed) in entries {
+                    desired.insert(parse_address(&address)?, allowed)?;
+                }
+            }
+        }
+        Ok(desired)
+    }
+
+    fn insert(&mut self, address: Address, allowed: bool) -> Result<(), anyhow::Error> {
+        match self.entries.insert(address, allowed) {
+            Some(previous) if previous != allowed => {
+                anyhow::bail!("{:?} is listed as both allowed and revoked", address)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn parse_address(address: &str) -> Result<Address, anyhow::Error> {
+    address
+        .trim()
+        .trim_start_matches("0x")
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid address {}", address))
+}
+
+fn parse_bool(flag: &str) -> Option<bool> {
+    match flag.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "1" | "allow" => Some(true),
+        "false" | "no" | "0" | "revoke" => Some(false),
+        _ => None,
+    }
+}
+
+/// The last `setAddressWhitelist` call for an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WhitelistRecord {
+    pub allowed: bool,
+    pub block_number: u64,
+    pub transaction_hash: H256,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WhitelistDiff {
+    pub grant: Vec<Address>,
+    pub revoke: Vec<Address>,
+    pub unchanged: usize,
+}
+
+impl WhitelistDiff {
+    /// Compares the desired whitelist with the current state. Addresses that
+    /// are whitelisted but not listed are revoked only with `prune`.
+    pub fn compute(
+        desired: &DesiredWhitelist,
+        current: &BTreeMap<Address, bool>,
+        prune: bool,
+    ) -> Self {
+        let mut diff = WhitelistDiff::default();
+        let addresses: BTreeSet<_> = desired.entries.keys().chain(current.keys()).collect();
+        for address in addresses {
+            let is_allowed = current.get(address).copied().unwrap_or(false);
+            let should_allow = match desired.entries.get(address) {
+                Some(allowed) => *allowed,
+                None if prune => false,
+                None => is_allowed,
+            };
+            match (is_allowed, should_allow) {
+                (false, true) => diff.grant.push(*address),
+                (true, false) => diff.revoke.push(*address),
+                _ => diff.unchanged += 1,
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.grant.is_empty() && self.revoke.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ChangeState {
+    Pending,
+    /// `transaction_hash` is `None` when the chain already had the flag.
+    Applied {
+        transaction_hash: Option<H256>,
+    },
+    Failed {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WhitelistChange {
+    pub address: Address,
+    pub allow: bool,
+    #[serde(flatten)]
+    pub state: ChangeState,
+}
+
+/// The transactions a diff needs, saved between runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WhitelistPlan {
+    pub changes: Vec<WhitelistChange>,
+}
+
+impl WhitelistPlan {
+    /// Grants first, then revocations, each in address order.
+    pub fn from_diff(diff: &WhitelistDiff) -> Self {
+        let change = |address: &Address, allow| WhitelistChange {
+            address: *address,
+            allow,
+            state: ChangeState::Pending,
+        };
+        WhitelistPlan {
+            changes: diff
+                .grant
+                .iter()
+                .map(|address| change(address, true))
+                .chain(diff.revoke.iter().map(|address| change(address, false)))
+                .collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        state::load(path)?.ok_or_else(|| anyhow::anyhow!("no plan at {}", path.display()))
+    }
+
+    /// Writes the plan atomically, so a crash never leaves a torn file.
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        state::save(self, path)
+    }
+
+    /// Changes still to send, including failed ones.
+    pub fn remaining(&self) -> usize {
+        self.changes
+            .iter()
+            .filter(|change| !matches!(change.state, ChangeState::Applied { .. }))
+            .count()
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &WhitelistChange> {
+        self.changes
+            .iter()
+            .filter(|change| matches!(change.state, ChangeState::Failed { .. }))
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining() == 0
+    }
+}
+
+impl Client {
+    /// The last indexed `setAddressWhitelist` call per address in
+    /// `from_block..=to_block`.
+    pub async fn whitelist_history(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<BTreeMap<Address, WhitelistRecord>, anyhow::Error> {
+        let mut history = BTreeMap::new();
+        for call in self.contract_calls(from_block, to_block).await? {
+            if call.function != "setAddressWhitelist" {
+                continue;
+            }
+            if let [Token::Address(address), Token::Bool(allowed)] = call.inputs[..] {
+                history.insert(
+                    address,
+                    WhitelistRecord {
+                        allowed,
+                        block_number: call.block_number,
+                        transaction_hash: call.transaction_hash,
+                    },
+                );
+            }
+        }
+        Ok(history)
+    }
+
+    /// Diffs `desired` against the contract. Every desired or historically
+    /// set address is read from one block, so the history only decides which
+    /// unlisted addresses `prune` looks at.
+    pub async fn whitelist_diff(
+        &self,
+        desired: &DesiredWhitelist,
+        history: &BTreeMap<Address, WhitelistRecord>,
+        prune: bool,
+    ) -> Result<WhitelistDiff, anyhow::Error> {
+        let client = match self.block() {
+            Some(_) => self.clone(),
+            None => self.snapshot().await?,
+        };
+        let mut current = BTreeMap::new();
+        for address in desired.entries.keys().chain(history.keys()) {
+            if !current.contains_key(address) {
+                let (allowed,) = client.address_whitelist(*address).await?;
+                current.insert(*address, allowed);
+            }
+        }
+        Ok(WhitelistDiff::compute(desired, &current, prune))
+    }
+
+    /// Sends the plan's pending and failed changes in order, saving it to
+    /// `path` after each one. Changes the chain already reflects are marked
+    /// applied without a transaction, which makes re-running after a crash
+    /// safe. `progress` gets the number of changes handled so far, the total
+    /// and the change just handled.
+    pub async fn apply_whitelist_plan(
+        &self,
+        plan: &mut WhitelistPlan,
+        path: Option<&Path>,
+        mut progress: impl FnMut(usize, usize, &WhitelistChange),
+    ) -> Result<(), anyhow::Error> {
+        let (owner,) = self.owner().await?;
+        if owner != self.address() {
+            anyhow::bail!(
+                "only the owner {:?} can change the whitelist, not {:?}",
+                owner,
+                self.address()
+            );
+        }
+        let total = plan.changes.len();
+        for index in 0..total {
+            let change = &mut plan.changes[index];
+            if matches!(change.state, ChangeState::Applied { .. }) {
+                continue;
+            }
+            change.state = match self.address_whitelist(change.address).await {
+                Ok((allowed,)) if allowed == change.allow => ChangeState::Applied {
+                    transaction_hash: None,
+                },
+                Ok(_) => match self
+                    .calls()
+                    .set_address_whitelist(change.address, change.allow)
+                    .await
+                {
+                    Ok(receipt) if receipt.status == Some(1.into()) => ChangeState::Applied {
+                        transaction_hash: Some(receipt.transaction_hash),
+                    },
+                    Ok(receipt) => ChangeState::Failed {
+                        reason: format!("reverted in {:?}", receipt.transaction_hash),
+                    },
+                    Err(e) => ChangeState::Failed {
+                        reason: e.to_string(),
+                    },
+                },
+                Err(e) => ChangeState::Failed {
+                    reason: format!("failed to read the whitelist: {}", e),
+                },
+            };
+            if let Some(path) = path {
+                plan.save(path)?;
+            }
+            progress(index + 1, total, &plan.changes[index]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    #[test]
+    fn test_parse_desired() {
+        let csv = "address,allowed\n\
+                   0x0000000000000000000000000000000000000001\n\
+                   # revoked\n\
+                   0000000000000000000000000000000000000002, false\n\n";
+        let desired = DesiredWhitelist::parse(csv).unwrap();
+        assert_eq!(
+            desired.entries,
+            BTreeMap::from([(address(1), true), (address(2), false)])
+        );
+        let json = r#"["0x0000000000000000000000000000000000000001",
+            {"address": "0x0000000000000000000000000000000000000002", "allowed": false}]"#;
+        assert_eq!(DesiredWhitelist::parse(json).unwrap(), desired);
+        let map = r#"{"0x0000000000000000000000000000000000000001": true,
+            "0x0000000000000000000000000000000000000002": false}"#;
+        assert_eq!(DesiredWhitelist::parse(map).unwrap(), desired);
+
+        assert!(DesiredWhitelist::from_csv("0x01\nnot-an-address").is_err());
+        assert!(DesiredWhitelist::from_csv(
+            "0x0000000000000000000000000000000000000001,true\n\
+             0x0000000000000000000000000000000000000001,false"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_diff_and_plan() {
+        let desired = DesiredWhitelist {
+            entries: BTreeMap::from([(address(1), true), (address(2), true), (address(3), false)]),
+        };
+        let current = BTreeMap::from([
+            (address(1), true),
+            (address(3), true),
+            (address(4), true),
+            (address(5), false),
+        ]);
+        let diff = WhitelistDiff::compute(&desired, &current, false);
+        assert_eq!(diff.grant, vec![address(2)]);
+        assert_eq!(diff.revoke, vec![address(3)]);
+        assert_eq!(diff.unchanged, 3);
+        let pruned = WhitelistDiff::compute(&desired, &current, true);
+        assert_eq!(pruned.revoke, vec![address(3), address(4)]);
+
+        let mut plan = WhitelistPlan::from_diff(&pruned);
+        assert_eq!(plan.remaining(), 3);
+        assert!(plan.changes[0].allow);
+        plan.changes[0].state = ChangeState::Applied {
+            transaction_hash: Some(H256::zero()),
+        };
+        plan.changes[1].state = ChangeState::Failed {
+            reason: "reverted".to_string(),
+        };
+        assert_eq!(plan.remaining(), 2);
+        assert_eq!(plan.failed().count(), 1);
+
+        let path =
+            std::env::temp_dir().join(format!("dep-whitelist-plan-{}.json", std::process::id()));
+        plan.save(&path).unwrap();
+        assert_eq!(WhitelistPlan::load(&path).unwrap(), plan);
+        fs::remove_file(path).unwrap();
+    }
+}